@@ -1,20 +1,32 @@
 use crate::{
-    client::error::Result,
-    client::ui,
-    data::{self, CanvasColor, Coord, Line, Message},
-    message::{InitialState, ToClientMsg, ToServerMsg},
-    server::skribbl::{PlayerState, SkribblState},
+    client::error::{Error, Result},
+    client::ui::{self, ColorSupport, ToolbarItem, Viewport},
+    data::{
+        self, Canvas, CanvasColor, Coord, DrawOp, Fill, History, Image, Line, Message, StrokeBatch,
+        BRUSH_SIZES,
+    },
+    export, import,
+    message::{
+        Capability, Encoding, Hello, InitialState, ToClientMsg, ToServerMsg, PROTOCOL_VERSION,
+    },
+    replay::Recorder,
+    server::{
+        lobby::LobbyState,
+        skribbl::{PlayerState, SkribblState},
+    },
     ClientEvent,
 };
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseEvent};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent};
 use futures_util::sink::SinkExt;
 use futures_util::stream::StreamExt;
 
 use data::{CommandMsg, Username};
+use std::time::Duration;
+use tokio::sync::oneshot;
 use tokio_tungstenite::WebSocketStream;
-use tui::{backend::Backend, Terminal};
+use tui::{backend::Backend, layout::Rect, Terminal};
 
-const PALETTE: [CanvasColor; 16] = [
+const PALETTE: [CanvasColor; 32] = [
     CanvasColor::White,
     CanvasColor::Gray,
     CanvasColor::DarkGray,
@@ -31,28 +43,204 @@ const PALETTE: [CanvasColor; 16] = [
     CanvasColor::LightCyan,
     CanvasColor::Magenta,
     CanvasColor::LightMagenta,
+    CanvasColor::Rgb(255, 136, 0),
+    CanvasColor::Rgb(255, 204, 153),
+    CanvasColor::Rgb(139, 69, 19),
+    CanvasColor::Rgb(210, 140, 70),
+    CanvasColor::Rgb(255, 105, 180),
+    CanvasColor::Rgb(255, 182, 193),
+    CanvasColor::Rgb(128, 0, 128),
+    CanvasColor::Rgb(181, 126, 220),
+    CanvasColor::Rgb(128, 0, 0),
+    CanvasColor::Rgb(250, 128, 114),
+    CanvasColor::Rgb(0, 100, 0),
+    CanvasColor::Rgb(128, 128, 0),
+    CanvasColor::Rgb(0, 0, 128),
+    CanvasColor::Rgb(135, 206, 235),
+    CanvasColor::Rgb(0, 128, 128),
+    CanvasColor::Rgb(255, 215, 0),
 ];
 
+/// how often the points of the stroke that's being drawn are sent to the server
+const STROKE_FLUSH_INTERVAL: Duration = Duration::from_millis(30);
+
+/// how often to try reconnecting after losing the connection, and how long to wait between attempts
+const RECONNECT_ATTEMPTS: u32 = 10;
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+const COMMAND_HELP: &str = "!ready, !start, !skip, !restart, \
+                            !kick <name>, !ban <name>, !mute <name>, !unmute <name>, \
+                            !color <#rrggbb or 0-255>, !export <file.png or file.svg> [scale], \
+                            !import <file.png or file.jpg>";
+
+/// what clicking on the canvas does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Brush,
+    /// paint cells back to the background, like drawing with the right mouse button
+    Eraser,
+    /// fill the area around the clicked cell
+    Fill,
+    /// a straight line between where the mouse was pressed and released
+    Line,
+    Rectangle,
+    FilledRectangle,
+    Ellipse,
+}
+
+impl Tool {
+    /// the label of the tool's button in the toolbar
+    pub fn label(&self) -> char {
+        match self {
+            Tool::Brush => 'B',
+            Tool::Eraser => 'E',
+            Tool::Fill => 'F',
+            Tool::Line => 'L',
+            Tool::Rectangle => 'R',
+            Tool::FilledRectangle => '#',
+            Tool::Ellipse => 'O',
+        }
+    }
+
+    /// the points to connect to draw this tool's shape between two corners,
+    /// or None if the tool doesn't draw shapes
+    pub fn shape_points(&self, start: Coord, end: Coord) -> Option<Vec<Coord>> {
+        let (left, right) = (start.0.min(end.0), start.0.max(end.0));
+        let (top, bottom) = (start.1.min(end.1), start.1.max(end.1));
+        match self {
+            Tool::Brush | Tool::Eraser | Tool::Fill => None,
+            Tool::Line => Some(vec![start, end]),
+            Tool::Rectangle => Some(vec![
+                Coord(left, top),
+                Coord(right, top),
+                Coord(right, bottom),
+                Coord(left, bottom),
+                Coord(left, top),
+            ]),
+            Tool::FilledRectangle => {
+                // zig-zag through the rows, so the whole rectangle is a single stroke
+                let mut points = Vec::new();
+                for y in top..=bottom {
+                    if (y - top) % 2 == 0 {
+                        points.extend_from_slice(&[Coord(left, y), Coord(right, y)]);
+                    } else {
+                        points.extend_from_slice(&[Coord(right, y), Coord(left, y)]);
+                    }
+                }
+                Some(points)
+            }
+            Tool::Ellipse => {
+                let (center_x, center_y) =
+                    ((left + right) as f64 / 2.0, (top + bottom) as f64 / 2.0);
+                let (radius_x, radius_y) =
+                    ((right - left) as f64 / 2.0, (bottom - top) as f64 / 2.0);
+                // roughly one point every few cells along the outline
+                let steps = ((radius_x + radius_y) * 2.0).max(8.0) as usize;
+                let mut points: Vec<Coord> = (0..=steps)
+                    .map(|step| {
+                        let angle = step as f64 / steps as f64 * std::f64::consts::PI * 2.0;
+                        Coord(
+                            (center_x + radius_x * angle.cos()).round() as u16,
+                            (center_y + radius_y * angle.sin()).round() as u16,
+                        )
+                    })
+                    .collect();
+                points.dedup();
+                Some(points)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AppCanvas {
     pub palette: Vec<CanvasColor>,
-    pub lines: Vec<data::Line>,
+    pub tools: Vec<Tool>,
+    pub brush_sizes: Vec<u16>,
+    /// everything drawn on the canvas, in the same state as the server's
+    pub history: History,
+    /// parts of our own strokes the server didn't send back yet, shown on top of the canvas
+    pub preview: Vec<StrokeBatch>,
+    /// the shape that's being dragged out, only sent to the server once the mouse is released
+    pub shape: Option<StrokeBatch>,
     pub dimensions: (usize, usize),
 }
 
 impl AppCanvas {
-    fn new(dimensions: (usize, usize), lines: Vec<data::Line>) -> Self {
+    /// a canvas that's only looked at, without anything to draw with in the toolbar
+    pub fn view_only(canvas: Canvas) -> Self {
         AppCanvas {
-            lines,
-            dimensions,
+            palette: Vec::new(),
+            tools: Vec::new(),
+            brush_sizes: Vec::new(),
+            ..AppCanvas::new(canvas, Vec::new())
+        }
+    }
+
+    fn new(canvas: Canvas, ops: Vec<DrawOp>) -> Self {
+        AppCanvas {
+            dimensions: canvas.dimensions(),
+            history: History::new(canvas, ops),
+            preview: Vec::new(),
+            shape: None,
             palette: PALETTE.to_vec(),
+            tools: vec![
+                Tool::Brush,
+                Tool::Eraser,
+                Tool::Fill,
+                Tool::Line,
+                Tool::Rectangle,
+                Tool::FilledRectangle,
+                Tool::Ellipse,
+            ],
+            brush_sizes: BRUSH_SIZES.to_vec(),
         }
     }
 }
 
 impl AppCanvas {
-    pub fn draw_line(&mut self, line: Line) {
-        self.lines.push(line);
+    /// apply an operation the server sent. every client applies them in the same order,
+    /// so everyone ends up with the same pixels.
+    pub fn apply(&mut self, op: DrawOp) {
+        if let DrawOp::Stroke(stroke) = &op {
+            // once the server caught up with our own stroke, it doesn't need to be previewed anymore
+            self.preview.retain(|preview| {
+                preview.stroke_id != stroke.stroke_id
+                    || preview.points.last() != stroke.points.last()
+            });
+        }
+        self.history.apply(op);
+    }
+
+    /// show a part of a stroke we're drawing until the server sends it back
+    pub fn preview_stroke(&mut self, segment: StrokeBatch) {
+        let segment = match self.preview.last_mut() {
+            Some(last) => match last.try_merge(segment) {
+                Ok(()) => return,
+                Err(segment) => segment,
+            },
+            None => segment,
+        };
+        self.preview.push(segment);
+    }
+
+    /// remove the stroke or fill with the given id
+    pub fn remove(&mut self, id: u64) {
+        self.history.remove(id);
+        self.preview.retain(|stroke| stroke.stroke_id != id);
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+        self.preview.clear();
+        self.shape = None;
+    }
+
+    pub fn preview_lines(&self) -> impl Iterator<Item = Line> + '_ {
+        self.preview
+            .iter()
+            .chain(self.shape.iter())
+            .flat_map(StrokeBatch::lines)
     }
 }
 
@@ -68,21 +256,83 @@ pub struct App {
     pub chat: Chat,
     pub session: ServerSession,
     pub last_mouse_pos: Option<Coord>,
+    /// the points of the stroke we're drawing that weren't sent to the server yet
+    pub pending_stroke: Option<StrokeBatch>,
+    /// where the mouse was pressed while drawing a shape
+    pub shape_start: Option<Coord>,
     pub current_color: CanvasColor,
+    pub current_tool: Tool,
+    pub current_brush_size: u16,
     pub game_state: Option<SkribblState>,
+    pub lobby_state: Option<LobbyState>,
+    pub host: Option<Username>,
     pub remaining_time: Option<u32>,
+    /// the words we can choose from, while it's our turn to choose what to draw
+    pub word_choices: Option<Vec<String>>,
+    /// where the word picker was last drawn, used to map clicks to the chosen word
+    pub word_picker_area: Option<Rect>,
+    /// where the inside of the canvas was last drawn, used to map clicks to the toolbar
+    pub canvas_area: Option<Rect>,
+    /// where the canvas itself was last drawn, used to map the mouse to canvas cells
+    pub viewport: Option<Viewport>,
+    /// draw the canvas with half block characters, showing twice as many rows
+    pub half_blocks: bool,
+    pub color_support: ColorSupport,
+    /// where to record the messages we get from the server to, if anywhere
+    pub recorder: Option<Recorder>,
 }
 
 impl App {
     pub fn new(session: ServerSession, initial_state: InitialState) -> App {
-        App {
-            canvas: AppCanvas::new(initial_state.dimensions, initial_state.lines),
+        let mut app = App {
+            canvas: AppCanvas::new(Canvas::new(initial_state.canvas.dimensions()), Vec::new()),
             chat: Chat::default(),
             last_mouse_pos: None,
+            pending_stroke: None,
+            shape_start: None,
             current_color: CanvasColor::White,
-            game_state: initial_state.skribbl_state,
+            current_tool: Tool::Brush,
+            current_brush_size: BRUSH_SIZES[0],
+            game_state: None,
+            lobby_state: None,
+            host: None,
             session,
             remaining_time: None,
+            word_choices: None,
+            word_picker_area: None,
+            canvas_area: None,
+            viewport: None,
+            half_blocks: false,
+            color_support: ColorSupport::detect(),
+            recorder: None,
+        };
+        app.apply_initial_state(initial_state);
+        app
+    }
+
+    /// replace everything we know about the room with the state the server sent on (re)joining
+    pub fn apply_initial_state(&mut self, initial_state: InitialState) {
+        self.canvas = AppCanvas::new(initial_state.canvas, initial_state.ops);
+        self.chat.messages = initial_state.messages;
+        self.game_state = initial_state.skribbl_state;
+        self.lobby_state = initial_state.lobby_state;
+        self.host = initial_state.host;
+        self.remaining_time = None;
+        self.word_choices = None;
+        self.last_mouse_pos = None;
+        self.pending_stroke = None;
+        self.shape_start = None;
+    }
+
+    /// add a message to the recording, stopping it if the message can't be written
+    fn record(&mut self, msg: &ToClientMsg) {
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(err) = recorder.record(msg) {
+                self.recorder = None;
+                self.chat
+                    .messages
+                    .push(Message::SystemMsg(format!("Stopped recording: {}", err)));
+            }
         }
     }
 
@@ -99,37 +349,118 @@ impl App {
             .unwrap_or(true)
     }
 
+    async fn choose_word(&mut self, idx: usize) -> Result<()> {
+        let choice_count = self
+            .word_choices
+            .as_ref()
+            .map_or(0, |choices| choices.len());
+        if idx < choice_count {
+            self.session.send(ToServerMsg::ChooseWord(idx)).await?;
+            self.word_choices = None;
+        }
+        Ok(())
+    }
+
     pub async fn handle_mouse_event(&mut self, evt: MouseEvent) -> Result<()> {
         if !self.is_drawing() {
             return Ok(());
         }
 
-        let dimensions = self.canvas.dimensions;
+        // while choosing a word, the word picker covers the canvas
+        if self.word_choices.is_some() {
+            if let (MouseEvent::Down(_, x, y, _), Some(area)) = (evt, self.word_picker_area) {
+                if x >= area.x && x < area.x + area.width && y > area.y {
+                    self.choose_word((y - area.y - 1) as usize).await?;
+                }
+            }
+            return Ok(());
+        }
+
+        let toolbar_width = self.canvas_area.map_or(0, |area| area.width);
         match evt {
-            MouseEvent::Down(_, x, y, _) => {
-                if y == 0 {
-                    let swatch_size = dimensions.0 / self.canvas.palette.len() as usize;
-                    let selected_color = self.canvas.palette.get(x as usize / swatch_size);
-                    match selected_color {
-                        Some(color) => self.current_color = color.clone(),
-                        _ => {}
+            MouseEvent::Down(_, x, y, _) if y < ui::toolbar_rows(&self.canvas, toolbar_width) => {
+                match ui::toolbar_item_at(&self.canvas, toolbar_width, x, y) {
+                    Some(ToolbarItem::Color(idx)) => self.current_color = self.canvas.palette[idx],
+                    Some(ToolbarItem::Tool(idx)) => self.current_tool = self.canvas.tools[idx],
+                    Some(ToolbarItem::BrushSize(idx)) => {
+                        self.current_brush_size = self.canvas.brush_sizes[idx]
                     }
+                    None => {}
+                }
+            }
+            MouseEvent::Down(button, x, y, _) => {
+                let mouse_pos = match self.viewport {
+                    Some(viewport) if viewport.contains(x, y) => viewport.canvas_coord(x, y),
+                    _ => return Ok(()),
+                };
+                if button == MouseButton::Right
+                    || matches!(self.current_tool, Tool::Brush | Tool::Eraser)
+                {
+                    self.last_mouse_pos = Some(mouse_pos);
+                    self.pending_stroke = Some(StrokeBatch::new(
+                        rand::random(),
+                        self.stroke_color(button),
+                        self.current_brush_size,
+                        mouse_pos,
+                    ));
+                } else if self.current_tool == Tool::Fill {
+                    let fill = Fill {
+                        id: rand::random(),
+                        origin: mouse_pos,
+                        color: self.current_color,
+                    };
+                    self.session.send(ToServerMsg::Fill(fill)).await?;
                 } else {
-                    self.last_mouse_pos = Some(Coord(x, y));
+                    self.shape_start = Some(mouse_pos);
+                    self.update_shape(mouse_pos);
                 }
             }
             MouseEvent::Up(_, _, _, _) => {
+                self.flush_stroke().await?;
                 self.last_mouse_pos = None;
+                self.pending_stroke = None;
+                self.shape_start = None;
+                if let Some(shape) = self.canvas.shape.take() {
+                    self.session
+                        .send(ToServerMsg::Stroke(shape.clone()))
+                        .await?;
+                    self.canvas.preview_stroke(shape);
+                }
+            }
+            // while dragging, the mouse sticks to the edge of the canvas when it leaves it
+            MouseEvent::Drag(_, x, y, _) if self.shape_start.is_some() => {
+                if let (Some(viewport), Some(start)) = (self.viewport, self.shape_start) {
+                    self.update_shape(viewport.drag_coord(x, y, start));
+                }
             }
-            MouseEvent::Drag(_, x, y, _) => {
-                let mouse_pos = Coord(x, y);
-                let line = Line::new(
-                    self.last_mouse_pos.unwrap_or(mouse_pos),
-                    mouse_pos,
-                    self.current_color,
-                );
-                self.canvas.draw_line(line);
-                self.session.send(ToServerMsg::NewLine(line)).await?;
+            MouseEvent::Drag(button, x, y, _)
+                if button == MouseButton::Right
+                    || matches!(self.current_tool, Tool::Brush | Tool::Eraser) =>
+            {
+                let mouse_pos = match (self.viewport, self.last_mouse_pos) {
+                    (Some(viewport), Some(last_mouse_pos)) => {
+                        viewport.drag_coord(x, y, last_mouse_pos)
+                    }
+                    (Some(viewport), None) => viewport.canvas_coord(x, y),
+                    (None, _) => return Ok(()),
+                };
+                let last_mouse_pos = self.last_mouse_pos.unwrap_or(mouse_pos);
+                let (current_color, current_brush_size) =
+                    (self.stroke_color(button), self.current_brush_size);
+                let stroke = self.pending_stroke.get_or_insert_with(|| {
+                    StrokeBatch::new(
+                        rand::random(),
+                        current_color,
+                        current_brush_size,
+                        last_mouse_pos,
+                    )
+                });
+                stroke.points.push(mouse_pos);
+
+                let mut segment =
+                    StrokeBatch::new(stroke.stroke_id, stroke.color, stroke.width, last_mouse_pos);
+                segment.points.push(mouse_pos);
+                self.canvas.preview_stroke(segment);
                 self.last_mouse_pos = Some(mouse_pos);
             }
             _ => {}
@@ -137,8 +468,110 @@ impl App {
         Ok(())
     }
 
+    /// the color of strokes drawn with the given mouse button, or None if they erase
+    fn stroke_color(&self, button: MouseButton) -> Option<CanvasColor> {
+        if button == MouseButton::Right || self.current_tool == Tool::Eraser {
+            None
+        } else {
+            Some(self.current_color)
+        }
+    }
+
+    /// stretch the shape that's being drawn from where the mouse was pressed to `end`
+    fn update_shape(&mut self, end: Coord) {
+        let start = match self.shape_start {
+            Some(start) => start,
+            None => return,
+        };
+        if let Some(points) = self.current_tool.shape_points(start, end) {
+            let id = self
+                .canvas
+                .shape
+                .as_ref()
+                .map_or_else(rand::random, |shape| shape.stroke_id);
+            self.canvas.shape = Some(StrokeBatch {
+                stroke_id: id,
+                color: Some(self.current_color),
+                width: self.current_brush_size,
+                points,
+            });
+        }
+    }
+
+    /// send the points drawn since the last flush to the server.
+    /// the last point is kept, so the next batch continues from there.
+    async fn flush_stroke(&mut self) -> Result<()> {
+        if let Some(stroke) = &mut self.pending_stroke {
+            if stroke.points.len() > 1 {
+                let last_point = stroke.points[stroke.points.len() - 1];
+                let next =
+                    StrokeBatch::new(stroke.stroke_id, stroke.color, stroke.width, last_point);
+                let batch = std::mem::replace(stroke, next);
+                self.session.send(ToServerMsg::Stroke(batch)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// save what's on the canvas to a file, given the arguments of the `!export` command
+    fn export(&mut self, args: &str) {
+        let mut args = args.split_whitespace();
+        let path = args.next().unwrap_or_default();
+        let scale = match args.next().map(str::parse) {
+            Some(Ok(scale)) if (1..=export::MAX_SCALE).contains(&scale) => scale,
+            Some(_) => {
+                self.chat.messages.push(Message::SystemMsg(format!(
+                    "The scale has to be a number of pixels per cell, from 1 to {}",
+                    export::MAX_SCALE
+                )));
+                return;
+            }
+            None => export::DEFAULT_SCALE,
+        };
+        let msg = match export::save(&self.canvas.history, std::path::Path::new(path), scale) {
+            Ok(()) => format!("Saved the drawing to {}", path),
+            Err(err) => format!("Could not save the drawing: {}", err),
+        };
+        self.chat.messages.push(Message::SystemMsg(msg));
+    }
+
+    /// paste an image file onto the canvas, given the argument of the `!import` command
+    async fn import(&mut self, path: &str) -> Result<()> {
+        if self.game_state.is_some() || self.lobby_state.is_some() {
+            self.chat.messages.push(Message::SystemMsg(
+                "Images can only be imported in free draw rooms".to_string(),
+            ));
+            return Ok(());
+        }
+        let path = path.trim();
+        match import::load(std::path::Path::new(path)) {
+            Ok(bitmap) => {
+                let pixels =
+                    import::quantize(&bitmap, self.canvas.dimensions, &self.canvas.palette);
+                let image = Image {
+                    id: rand::random(),
+                    pixels,
+                };
+                self.session.send(ToServerMsg::Image(image)).await?;
+                self.chat
+                    .messages
+                    .push(Message::SystemMsg(format!("Imported {}", path)));
+            }
+            Err(err) => self.chat.messages.push(Message::SystemMsg(format!(
+                "Could not import {}: {}",
+                path, err
+            ))),
+        }
+        Ok(())
+    }
+
     pub async fn handle_chat_key_event(&mut self, event: &KeyEvent) -> Result<()> {
         let KeyEvent { modifiers, code } = event;
+        if let (Some(_), KeyCode::Char(c)) = (&self.word_choices, code) {
+            if let Some(number) = c.to_digit(10).filter(|&number| number > 0) {
+                return self.choose_word(number as usize - 1).await;
+            }
+        }
         match code {
             KeyCode::Enter => {
                 if self.chat.input.trim().is_empty() {
@@ -147,13 +580,27 @@ impl App {
                 }
 
                 let msg_content = self.chat.input.clone();
-                if msg_content.starts_with("!") {
-                    if msg_content.starts_with("!kick ") {
-                        let msg_without_cmd =
-                            msg_content.trim_start_matches("!kick ").trim().to_string();
-                        let command = CommandMsg::KickPlayer(Username::from(msg_without_cmd));
-                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
-                    };
+                if let Some(color) = msg_content.trim().strip_prefix("!color ") {
+                    // picking a color only matters to us, so the server never sees this command
+                    match color.trim().parse() {
+                        Ok(color) => self.current_color = color,
+                        Err(err) => self.chat.messages.push(Message::SystemMsg(err)),
+                    }
+                } else if let Some(args) = msg_content.trim().strip_prefix("!export ") {
+                    self.export(args);
+                } else if let Some(path) = msg_content.trim().strip_prefix("!import ") {
+                    self.import(path).await?;
+                } else if msg_content.starts_with("!") {
+                    match CommandMsg::parse(&msg_content) {
+                        Some(command) => {
+                            self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                        }
+                        None => self.chat.messages.push(Message::SystemMsg(format!(
+                            "Unknown command \"{}\". Available commands: {}",
+                            msg_content.trim(),
+                            COMMAND_HELP
+                        ))),
+                    }
                 } else {
                     let message =
                         Message::UserMsg(self.session.username.clone(), self.chat.input.clone());
@@ -167,10 +614,21 @@ impl App {
             KeyCode::Char('h') if modifiers.contains(KeyModifiers::CONTROL) => {
                 self.chat.input.pop();
             }
+            KeyCode::Char('z')
+                if modifiers.contains(KeyModifiers::CONTROL) && self.is_drawing() =>
+            {
+                self.flush_stroke().await?;
+                self.session.send(ToServerMsg::Undo).await?;
+            }
+            KeyCode::Char('y')
+                if modifiers.contains(KeyModifiers::CONTROL) && self.is_drawing() =>
+            {
+                self.session.send(ToServerMsg::Redo).await?;
+            }
             KeyCode::Delete => {
                 if self.is_drawing() {
                     self.session.send(ToServerMsg::ClearCanvas).await?;
-                    self.canvas.lines.clear();
+                    self.canvas.clear();
                 }
             }
             KeyCode::Char(c) => {
@@ -189,28 +647,95 @@ impl App {
             ClientEvent::MouseInput(mouse_evt) => {
                 self.handle_mouse_event(mouse_evt).await?;
             }
-            ClientEvent::ServerMessage(m) => match m {
-                ToClientMsg::TimeChanged(new_time) => {
-                    self.remaining_time = Some(new_time);
-                }
-                ToClientMsg::NewMessage(message) => self.chat.messages.push(message),
-                ToClientMsg::NewLine(line) => {
-                    self.canvas.draw_line(line);
+            ClientEvent::ConnectionLost if self.session.supports(Capability::Resume) => {
+                self.chat.messages.push(Message::SystemMsg(
+                    "Lost the connection to the server, reconnecting...".to_string(),
+                ));
+                tokio::spawn(self.session.clone().reconnect());
+            }
+            ClientEvent::ConnectionLost => {
+                self.chat.messages.push(Message::SystemMsg(
+                    "Lost the connection to the server".to_string(),
+                ));
+            }
+            ClientEvent::Reconnected(session, initial_state) => {
+                if self.recorder.is_some() {
+                    self.record(&ToClientMsg::InitialState(initial_state.clone()));
                 }
-                ToClientMsg::SkribblStateChanged(new_state) => {
-                    self.game_state = Some(new_state);
+                self.session = session;
+                self.apply_initial_state(initial_state);
+                self.chat
+                    .messages
+                    .push(Message::SystemMsg("Reconnected".to_string()));
+            }
+            ClientEvent::ReconnectFailed(reason) => {
+                self.chat.messages.push(Message::SystemMsg(format!(
+                    "Could not reconnect: {}",
+                    reason
+                )));
+            }
+            ClientEvent::ServerMessage(m) => {
+                self.record(&m);
+                self.handle_server_message(m);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_server_message(&mut self, m: ToClientMsg) {
+        match m {
+            ToClientMsg::TimeChanged(new_time) => {
+                self.remaining_time = Some(new_time);
+            }
+            ToClientMsg::NewMessage(message) => self.chat.messages.push(message),
+            ToClientMsg::DrawOps(ops) | ToClientMsg::Redo(ops) => {
+                for op in ops {
+                    self.canvas.apply(op);
                 }
-                ToClientMsg::ClearCanvas => {
-                    self.canvas.lines.clear();
+            }
+            ToClientMsg::Undo(id) => {
+                self.canvas.remove(id);
+            }
+            ToClientMsg::SkribblStateChanged(new_state) => {
+                if !new_state.is_choosing_word() {
+                    self.word_choices = None;
                 }
-                ToClientMsg::GameOver(state) => {
-                    dbg!(state);
-                    panic!("Game over, I couldn't yet be bothered to implement this in a better way yet,...");
+                self.game_state = Some(new_state);
+                self.lobby_state = None;
+            }
+            ToClientMsg::LobbyStateChanged(new_state) => {
+                self.lobby_state = Some(new_state);
+            }
+            ToClientMsg::HostChanged(host) => {
+                self.host = host;
+            }
+            ToClientMsg::WordChoices(choices) => {
+                self.word_choices = Some(choices);
+            }
+            ToClientMsg::ClearCanvas => {
+                self.canvas.clear();
+            }
+            ToClientMsg::GameOver(state) => {
+                self.chat.messages.push(Message::SystemMsg(
+                    "Game over! Final standings:".to_string(),
+                ));
+                for (rank, (username, player)) in state.ranking().into_iter().enumerate() {
+                    self.chat.messages.push(Message::SystemMsg(format!(
+                        "{}. {}: {}",
+                        rank + 1,
+                        username,
+                        player.score
+                    )));
                 }
-                ToClientMsg::InitialState(_) => {}
-            },
+                self.game_state = None;
+                self.remaining_time = None;
+                self.word_choices = None;
+            }
+            // only expected while joining
+            ToClientMsg::Welcome(_)
+            | ToClientMsg::InitialState(_)
+            | ToClientMsg::JoinRejected(_) => {}
         }
-        Ok(())
     }
 
     pub async fn run<B: Backend>(
@@ -218,9 +743,17 @@ impl App {
         mut terminal: &mut Terminal<B>,
         mut chan: tokio::sync::mpsc::Receiver<ClientEvent>,
     ) -> Result<()> {
+        let mut flush_interval = tokio::time::interval(STROKE_FLUSH_INTERVAL);
         loop {
             ui::draw(self, &mut terminal)?;
-            if let Some(event) = chan.recv().await {
+            // flushing the stroke doesn't change what's shown, so only redraw after events
+            let event = loop {
+                tokio::select! {
+                    event = chan.recv() => break event,
+                    _ = flush_interval.tick() => self.flush_stroke().await?,
+                }
+            };
+            if let Some(event) = event {
                 self.handle_event(event).await?;
             } else {
                 break Ok(());
@@ -232,78 +765,187 @@ impl App {
 #[derive(Debug, Clone)]
 pub struct ServerSession {
     to_server_send: tokio::sync::mpsc::Sender<ToServerMsg>,
+    evt_send: tokio::sync::mpsc::Sender<ClientEvent>,
     pub username: Username,
+    addr: String,
+    room: Option<String>,
+    resume_token: String,
+    /// the capabilities the server agreed to in its welcome
+    capabilities: Vec<Capability>,
 }
 
 impl ServerSession {
     pub async fn establish_connection(
         addr: &str,
         username: Username,
-        mut evt_send: tokio::sync::mpsc::Sender<ClientEvent>,
+        room: Option<String>,
+        capabilities: Vec<Capability>,
+        evt_send: tokio::sync::mpsc::Sender<ClientEvent>,
+        mut recorder: Option<Recorder>,
     ) -> Result<App> {
-        let (to_server_send, mut to_server_recv) = tokio::sync::mpsc::channel::<ToServerMsg>(1);
+        let (session, initial_state, start_forwarding) =
+            ServerSession::connect(addr, username, room, capabilities, None, evt_send).await?;
+        if let Some(recorder) = &mut recorder {
+            recorder.record(&ToClientMsg::InitialState(initial_state.clone()))?;
+        }
+        let mut app = App::new(session, initial_state);
+        app.recorder = recorder;
+        let _ = start_forwarding.send(());
+        Ok(app)
+    }
+
+    /// a session that isn't connected to any server, used to replay recordings.
+    /// everything sent to it is dropped.
+    pub fn offline(username: Username) -> ServerSession {
+        let (to_server_send, _) = tokio::sync::mpsc::channel(1);
+        let (evt_send, _) = tokio::sync::mpsc::channel(1);
+        ServerSession {
+            to_server_send,
+            evt_send,
+            username,
+            addr: String::new(),
+            room: None,
+            resume_token: String::new(),
+            capabilities: Vec::new(),
+        }
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
 
-        let ws: WebSocketStream<_> = tokio_tungstenite::connect_async(addr)
-            .await
-            .expect("Could not connect to server")
-            .0;
+    /// try to resume this session on a new connection, until it works or we give up.
+    /// the result is sent back as a ClientEvent.
+    pub async fn reconnect(mut self) {
+        let mut result = Err(Error::ConnectionClosed);
+        for _ in 0..RECONNECT_ATTEMPTS {
+            tokio::time::delay_for(RECONNECT_DELAY).await;
+            result = ServerSession::connect(
+                &self.addr,
+                self.username.clone(),
+                self.room.clone(),
+                self.capabilities.clone(),
+                Some(self.resume_token.clone()),
+                self.evt_send.clone(),
+            )
+            .await;
+            match result {
+                Ok(_) | Err(Error::JoinRejected(_)) => break,
+                Err(_) => {}
+            }
+        }
+        match result {
+            Ok((session, initial_state, start_forwarding)) => {
+                // the state has to replace the old one before the messages sent after it are applied
+                let evt = ClientEvent::Reconnected(session, initial_state);
+                let _ = self.evt_send.send(evt).await;
+                let _ = start_forwarding.send(());
+            }
+            Err(err) => {
+                let evt = ClientEvent::ReconnectFailed(err.to_string());
+                let _ = self.evt_send.send(evt).await;
+            }
+        }
+    }
+
+    /// connect to the server and wait for the state of the game.
+    /// messages from the server are only forwarded as ClientEvents once the returned sender is used,
+    /// so they can't arrive before the state they apply to.
+    async fn connect(
+        addr: &str,
+        username: Username,
+        room: Option<String>,
+        capabilities: Vec<Capability>,
+        resume_token: Option<String>,
+        evt_send: tokio::sync::mpsc::Sender<ClientEvent>,
+    ) -> Result<(ServerSession, InitialState, oneshot::Sender<()>)> {
+        let (to_server_send, mut to_server_recv) = tokio::sync::mpsc::channel::<ToServerMsg>(16);
+
+        let ws: WebSocketStream<_> = tokio_tungstenite::connect_async(addr).await?.0;
         let (mut ws_send, mut ws_recv) = ws.split();
 
-        // first send the username to the server
-        ws_send
-            .send(tungstenite::Message::Text(username.clone().into()))
-            .await
-            .unwrap();
+        // first introduce ourselves to the server
+        let hello = ToServerMsg::Hello(Hello {
+            protocol_version: PROTOCOL_VERSION,
+            client_name: env!("CARGO_PKG_NAME").to_string(),
+            client_version: env!("CARGO_PKG_VERSION").to_string(),
+            username: username.clone(),
+            room: room.clone(),
+            capabilities,
+            resume_token,
+        });
+        ws_send.send(Encoding::Json.encode(&hello)).await?;
 
-        // and wait for the initial state
+        // and wait for the welcome and the initial state, unless the server doesn't let us in
+        let mut capabilities = Vec::new();
         let initial_state: InitialState = loop {
-            let msg = ws_recv.next().await;
-            if let Some(Ok(tungstenite::Message::Text(msg))) = msg {
-                if let Ok(ToClientMsg::InitialState(state)) = serde_json::from_str(&msg) {
-                    break state;
+            match ws_recv.next().await {
+                Some(Ok(tungstenite::Message::Close(_))) | None => {
+                    return Err(Error::ConnectionClosed)
                 }
+                Some(Err(err)) => return Err(err.into()),
+                Some(Ok(msg)) => match Encoding::decode(&msg) {
+                    Some(Ok(ToClientMsg::Welcome(welcome))) => capabilities = welcome.capabilities,
+                    Some(Ok(ToClientMsg::InitialState(state))) => break state,
+                    Some(Ok(ToClientMsg::JoinRejected(reason))) => {
+                        return Err(Error::JoinRejected(reason))
+                    }
+                    Some(Ok(_)) | None => {}
+                    // a server that doesn't know about the handshake
+                    Some(Err(_)) => return Err(Error::IncompatibleServer),
+                },
             }
         };
+        let encoding = Encoding::from_capabilities(&capabilities);
 
         // forward events to the server
         let send_handle = tokio::spawn(async move {
             loop {
                 let msg = to_server_recv.recv().await;
-                let msg = serde_json::to_string(&msg).unwrap();
-                if let Err(_) = ws_send.send(tungstenite::Message::Text(msg)).await {
+                if ws_send.send(encoding.encode(&msg)).await.is_err() {
                     break;
                 }
             }
         });
 
-        // and receive messages from the server
+        // and receive messages from the server, until the connection is gone
+        let mut server_evt_send = evt_send.clone();
+        let (start_forwarding, forwarding_started) = oneshot::channel();
         tokio::spawn(async move {
+            if forwarding_started.await.is_err() {
+                return;
+            }
             loop {
                 match ws_recv.next().await {
-                    Some(Ok(tungstenite::Message::Text(msg))) => {
-                        let msg = serde_json::from_str(&msg).unwrap();
-                        let _ = evt_send.send(ClientEvent::ServerMessage(msg)).await;
-                    }
-                    Some(Ok(tungstenite::Message::Close(_))) => {
+                    Some(Ok(tungstenite::Message::Close(_))) | Some(Err(_)) | None => {
                         break;
                     }
-                    _ => {}
+                    Some(Ok(msg)) => {
+                        if let Some(Ok(msg)) = Encoding::decode(&msg) {
+                            let _ = server_evt_send.send(ClientEvent::ServerMessage(msg)).await;
+                        }
+                    }
                 }
             }
             std::mem::drop(send_handle);
+            let _ = server_evt_send.send(ClientEvent::ConnectionLost).await;
         });
 
-        Ok(App::new(
-            ServerSession {
-                to_server_send,
-                username,
-            },
-            initial_state,
-        ))
+        let session = ServerSession {
+            to_server_send,
+            evt_send,
+            username,
+            addr: addr.to_string(),
+            room,
+            resume_token: initial_state.resume_token.clone(),
+            capabilities,
+        };
+        Ok((session, initial_state, start_forwarding))
     }
 
     pub async fn send(&mut self, message: ToServerMsg) -> Result<()> {
-        self.to_server_send.send(message).await?;
+        // while the connection is lost there's no one to send messages to, so they're dropped
+        let _ = self.to_server_send.send(message).await;
         Ok(())
     }
 }
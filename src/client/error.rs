@@ -1,3 +1,6 @@
+use crate::message::JoinRejectReason;
+use std::fmt::Display;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
@@ -6,6 +9,28 @@ pub enum Error {
     CrosstermError(crossterm::ErrorKind),
     IOError(std::io::Error),
     WebSocketError(tungstenite::error::Error),
+    /// the server didn't let us join
+    JoinRejected(JoinRejectReason),
+    /// the server closed the connection before we joined
+    ConnectionClosed,
+    /// the server sent something we don't understand while joining
+    IncompatibleServer,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::JoinRejected(reason) => write!(f, "Could not join: {}", reason),
+            Error::ConnectionClosed => write!(f, "The server closed the connection"),
+            Error::IncompatibleServer => {
+                write!(
+                    f,
+                    "The server is not compatible with this version of termibbl"
+                )
+            }
+            other => write!(f, "{:?}", other),
+        }
+    }
 }
 
 impl<T> From<tokio::sync::mpsc::error::SendError<T>> for Error {
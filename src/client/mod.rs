@@ -14,4 +14,21 @@ pub struct CliOpts {
     #[argh(option, short = 'a')]
     /// address of server to connect to.
     pub addr: String,
+
+    #[argh(option, short = 'r')]
+    /// name of the room to join. the room is created if it doesn't exist yet.
+    pub room: Option<String>,
+
+    #[argh(switch)]
+    /// send and receive json instead of the more compact binary messages, useful for debugging.
+    pub json: bool,
+
+    #[argh(switch)]
+    /// draw the canvas with half block characters, which shows twice as many details
+    /// but doesn't look right in every terminal font.
+    pub half_blocks: bool,
+
+    #[argh(option)]
+    /// record everything the server sends to the given file, to play it back with `termibbl replay`.
+    pub record: Option<std::path::PathBuf>,
 }
@@ -1,58 +1,91 @@
 use crate::{
-    client::app::{App, AppCanvas},
+    client::app::{App, AppCanvas, Tool},
     client::error::Result,
-    data::{Coord, Message},
-    server::skribbl::{PlayerState, SkribblState},
+    data::{CanvasColor, Coord, Message},
+    server::{
+        lobby::LobbyState,
+        skribbl::{PlayerState, SkribblState},
+    },
 };
 
 use super::Username;
+use std::collections::HashMap;
 use tui::{
     backend::Backend,
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Style},
-    widgets::{Block, Borders, List, Paragraph, Text, Widget},
+    widgets::{Block, Borders, Clear, List, Paragraph, Text, Widget},
     Terminal,
 };
 
+/// the width the sidebar gets at least, even if the canvas has to be scaled down for it
+const MIN_SIDEBAR_WIDTH: u16 = 30;
+
+/// split the screen into the canvas, including its border, and the sidebar next to it
+pub fn canvas_and_sidebar(size: Rect, dimensions: (usize, usize)) -> (Rect, Rect) {
+    // the canvas plus its border, or whatever is left next to the sidebar
+    let canvas_rect = Rect {
+        width: u16::min(
+            dimensions.0 as u16 + 2,
+            u16::max(size.width.saturating_sub(MIN_SIDEBAR_WIDTH), size.width / 2),
+        ),
+        height: u16::min(dimensions.1.div_ceil(ROWS_PER_CELL) as u16 + 2, size.height),
+        ..size
+    };
+    let sidebar_rect = Rect {
+        x: size.x + canvas_rect.width,
+        width: size.width - canvas_rect.width,
+        ..size
+    };
+    (canvas_rect, sidebar_rect)
+}
+
 pub fn draw<B: Backend>(app: &mut App, terminal: &mut Terminal<B>) -> Result<()> {
     let dimensions = app.canvas.dimensions;
+    let (canvas_rect, sidebar_rect) = canvas_and_sidebar(terminal.size()?, dimensions);
+    let canvas_area = canvas_block().inner(canvas_rect);
+    // a palette wrapped onto a second row covers the first line of the canvas
+    let wrapped_rows = toolbar_rows(&app.canvas, canvas_area.width) - 1;
+    let viewport = Viewport::new(
+        Rect {
+            y: canvas_area.y + wrapped_rows,
+            height: canvas_area.height.saturating_sub(wrapped_rows),
+            ..canvas_area
+        },
+        dimensions,
+    );
+    app.canvas_area = Some(canvas_area);
+    app.viewport = Some(viewport);
+    app.word_picker_area = app
+        .word_choices
+        .as_ref()
+        .map(|choices| word_picker_area(canvas_rect, choices));
+
     terminal.draw(|mut f| {
         use Constraint::*;
-        let size = f.size();
-        let main_chunks = Layout::default()
-            .direction(Direction::Horizontal)
-            .margin(0)
-            .constraints(
-                [
-                    Length(dimensions.0 as u16),
-                    Length(if size.width < dimensions.0 as u16 {
-                        size.width
-                    } else {
-                        size.width - dimensions.0 as u16
-                    }),
-                ]
-                .as_ref(),
-            )
-            .split(size);
-
         let canvas_widget = CanvasWidget::new(
             &app.canvas,
-            Block::default()
-                .borders(Borders::ALL)
-                .border_style(Style::default().fg(app.current_color.into())),
+            viewport,
+            app.half_blocks,
+            app.color_support,
+            app.current_tool,
+            app.current_brush_size,
+            canvas_block()
+                .border_style(Style::default().fg(app.color_support.color(app.current_color))),
         );
 
         let game_state_height = app
             .game_state
             .as_ref()
             .map(|x| x.player_states.len() + 3)
+            .or_else(|| app.lobby_state.as_ref().map(|x| x.players.len() + 3))
             .unwrap_or(0) as u16;
 
         let sidebar_chunks = Layout::default()
             .direction(Direction::Vertical)
             .margin(0)
             .constraints([Length(game_state_height), Percentage(100)].as_ref())
-            .split(main_chunks[1]);
+            .split(sidebar_rect);
 
         if let Some(skribbl_state) = app.game_state.as_mut() {
             let skribbl_widget = SkribblStateWidget::new(
@@ -62,14 +95,23 @@ pub fn draw<B: Backend>(app: &mut App, terminal: &mut Terminal<B>) -> Result<()>
                 Block::default().borders(Borders::NONE),
             );
             f.render_widget(skribbl_widget, sidebar_chunks[0]);
+        } else if let Some(lobby_state) = app.lobby_state.as_ref() {
+            let lobby_widget = LobbyWidget::new(
+                lobby_state,
+                &app.session.username,
+                app.host.as_ref(),
+                Block::default().borders(Borders::NONE),
+            );
+            f.render_widget(lobby_widget, sidebar_chunks[0]);
         }
 
-        let canvas_rect = Rect {
-            height: u16::min(dimensions.1 as u16, main_chunks[0].height),
-            ..main_chunks[0]
-        };
         f.render_widget(canvas_widget, canvas_rect);
 
+        if let (Some(choices), Some(area)) = (&app.word_choices, app.word_picker_area) {
+            f.render_widget(Clear, area);
+            f.render_widget(WordPickerWidget::new(choices), area);
+        }
+
         let displayed_messages = (&app.chat.messages)
             .iter()
             .filter(|msg| match msg {
@@ -96,39 +138,415 @@ pub fn draw<B: Backend>(app: &mut App, terminal: &mut Terminal<B>) -> Result<()>
     Ok(())
 }
 
+pub fn canvas_block<'a>() -> Block<'a> {
+    Block::default().borders(Borders::ALL)
+}
+
+/// width of a single button in the toolbar
+const TOOLBAR_BUTTON_WIDTH: u16 = 3;
+
+/// something that can be clicked in the toolbar at the top of the canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ToolbarItem {
+    Color(usize),
+    Tool(usize),
+    BrushSize(usize),
+}
+
+/// where the items of the toolbar are.
+/// the toolbar is made up of the palette, followed by the tools and brush sizes on the right.
+/// if the palette doesn't fit next to the buttons, it wraps onto the rows below them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ToolbarLayout {
+    /// width of a single color swatch
+    swatch_size: u16,
+    /// how many swatches are in the first row, next to the buttons
+    first_row_swatches: usize,
+    /// how many swatches are in each of the rows below the first one
+    swatches_per_row: usize,
+    /// how many of the buttons fit, the ones that don't are left out
+    buttons: usize,
+    /// the column the buttons start at
+    buttons_x: u16,
+}
+
+impl ToolbarLayout {
+    fn new(canvas: &AppCanvas, width: u16) -> Self {
+        let button_count = canvas.tools.len() + canvas.brush_sizes.len();
+        let buttons = usize::min(button_count, (width / TOOLBAR_BUTTON_WIDTH) as usize);
+        let buttons_x = width - TOOLBAR_BUTTON_WIDTH * buttons as u16;
+        let palette_width = buttons_x.saturating_sub(1);
+        let colors = canvas.palette.len();
+
+        let (swatch_size, first_row_swatches) = if colors == 0 {
+            (1, 0)
+        } else if palette_width as usize >= colors {
+            (palette_width / colors as u16, colors)
+        } else {
+            // split the palette evenly between two rows if it fits that way
+            let half = colors.div_ceil(2);
+            let swatch_size = u16::max(1, palette_width / half as u16);
+            (
+                swatch_size,
+                usize::min(half, (palette_width / swatch_size) as usize),
+            )
+        };
+        ToolbarLayout {
+            swatch_size,
+            first_row_swatches,
+            swatches_per_row: usize::max(1, (width / swatch_size) as usize),
+            buttons,
+            buttons_x,
+        }
+    }
+
+    /// how many rows the toolbar takes up to show all of the given number of colors
+    fn rows(&self, colors: usize) -> u16 {
+        let wrapped = colors.saturating_sub(self.first_row_swatches);
+        1 + wrapped.div_ceil(self.swatches_per_row) as u16
+    }
+
+    /// the column and row of the swatch of the given color
+    fn swatch_position(&self, idx: usize) -> (u16, u16) {
+        let (column, row) = match idx.checked_sub(self.first_row_swatches) {
+            None => (idx, 0),
+            Some(wrapped) => (
+                wrapped % self.swatches_per_row,
+                1 + wrapped / self.swatches_per_row,
+            ),
+        };
+        (column as u16 * self.swatch_size, row as u16)
+    }
+}
+
+/// how many rows at the top of the canvas the toolbar takes up
+pub fn toolbar_rows(canvas: &AppCanvas, width: u16) -> u16 {
+    ToolbarLayout::new(canvas, width).rows(canvas.palette.len())
+}
+
+/// the toolbar item at the given column and row of the toolbar, if any
+pub fn toolbar_item_at(canvas: &AppCanvas, width: u16, x: u16, y: u16) -> Option<ToolbarItem> {
+    let layout = ToolbarLayout::new(canvas, width);
+    if y == 0 && x >= layout.buttons_x {
+        let idx = ((x - layout.buttons_x) / TOOLBAR_BUTTON_WIDTH) as usize;
+        if idx >= layout.buttons {
+            None
+        } else if idx < canvas.tools.len() {
+            Some(ToolbarItem::Tool(idx))
+        } else {
+            Some(ToolbarItem::BrushSize(idx - canvas.tools.len()))
+        }
+    } else {
+        (0..canvas.palette.len())
+            .find(|&idx| {
+                let (swatch_x, row) = layout.swatch_position(idx);
+                row == y && (swatch_x..swatch_x + layout.swatch_size).contains(&x)
+            })
+            .map(ToolbarItem::Color)
+    }
+}
+
+/// which colors the terminal can show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSupport {
+    /// only the 16 basic colors
+    Ansi,
+    /// the 256 color palette
+    Indexed,
+    /// any rgb color
+    TrueColor,
+}
+
+impl ColorSupport {
+    /// guess what the terminal supports from the `COLORTERM` and `TERM` environment variables
+    pub fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorSupport::TrueColor
+        } else if term.contains("256color") {
+            ColorSupport::Indexed
+        } else {
+            ColorSupport::Ansi
+        }
+    }
+
+    /// the closest color to the given one that the terminal can show
+    pub fn color(self, color: CanvasColor) -> Color {
+        match self {
+            ColorSupport::Ansi => color.to_ansi(),
+            ColorSupport::Indexed => color.to_indexed(),
+            ColorSupport::TrueColor => color,
+        }
+        .into()
+    }
+}
+
+/// how many rows of the canvas are shown in one line of the terminal, when it isn't scaled down
+const ROWS_PER_CELL: usize = 2;
+
+/// where the canvas is shown on the screen.
+/// if the screen is too small for it, the canvas is scaled down, keeping its aspect ratio, and centered.
+#[derive(Debug, Clone, Copy)]
+pub struct Viewport {
+    /// the cells of the screen the canvas is shown in
+    pub area: Rect,
+    dimensions: (usize, usize),
+    /// how many canvas cells one screen cell covers horizontally, and one half of it vertically. at least 1
+    scale: f64,
+}
+
+impl Viewport {
+    /// fit a canvas of the given size into `available`
+    pub fn new(available: Rect, dimensions: (usize, usize)) -> Self {
+        let lines = dimensions.1 as f64 / ROWS_PER_CELL as f64;
+        let scale = f64::max(
+            1.0,
+            f64::max(
+                dimensions.0 as f64 / available.width.max(1) as f64,
+                lines / available.height.max(1) as f64,
+            ),
+        );
+        let width = u16::min((dimensions.0 as f64 / scale).ceil() as u16, available.width);
+        let height = u16::min((lines / scale).ceil() as u16, available.height);
+        Viewport {
+            area: Rect {
+                x: available.x + (available.width - width) / 2,
+                y: available.y + (available.height - height) / 2,
+                width,
+                height,
+            },
+            dimensions,
+            scale,
+        }
+    }
+
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.area.left()
+            && x < self.area.right()
+            && y >= self.area.top()
+            && y < self.area.bottom()
+    }
+
+    /// the canvas cell shown in the top half of the given screen cell, as the mouse can't point at half cells.
+    /// screen cells outside of the viewport are mapped to the closest cell on the edge of the canvas.
+    pub fn canvas_coord(&self, x: u16, y: u16) -> Coord {
+        self.half_cell_coord(x, y, 0)
+    }
+
+    /// the canvas cell the mouse is at while dragging it from `previous`.
+    /// the mouse only reports whole screen cells, so the half of the cell is guessed from the direction it moved in:
+    /// coming from above it enters the top half, coming from below the bottom half,
+    /// and moving sideways it stays in the half it was in, which lets strokes be drawn along every row.
+    pub fn drag_coord(&self, x: u16, y: u16, previous: Coord) -> Coord {
+        let top = self.half_cell_coord(x, y, 0);
+        let bottom = self.half_cell_coord(x, y, 1);
+        Coord(top.0, previous.1.clamp(top.1, bottom.1))
+    }
+
+    /// the canvas cell shown in the top (0) or bottom (1) half of the given screen cell
+    fn half_cell_coord(&self, x: u16, y: u16, half: usize) -> Coord {
+        let column = (x.saturating_sub(self.area.x) as f64 * self.scale) as usize;
+        let half_line = y.saturating_sub(self.area.y) as usize * ROWS_PER_CELL + half;
+        let row = (half_line as f64 * self.scale) as usize;
+        Coord(
+            column.min(self.dimensions.0.saturating_sub(1)) as u16,
+            row.min(self.dimensions.1.saturating_sub(1)) as u16,
+        )
+    }
+
+    /// all canvas cells that are shown in the top (0) or bottom (1) half of the given screen cell
+    fn canvas_cells(&self, x: u16, y: u16, half: usize) -> impl Iterator<Item = Coord> {
+        let scale = self.scale;
+        let span = move |start: usize, len: usize| {
+            let from = (start as f64 * scale) as usize;
+            let to = usize::max(((start + 1) as f64 * scale) as usize, from + 1);
+            from.min(len)..to.min(len)
+        };
+        let columns = span((x - self.area.x) as usize, self.dimensions.0);
+        let rows = span(
+            (y - self.area.y) as usize * ROWS_PER_CELL + half,
+            self.dimensions.1,
+        );
+        rows.flat_map(move |row| {
+            columns
+                .clone()
+                .map(move |column| Coord(column as u16, row as u16))
+        })
+    }
+}
+
 pub struct CanvasWidget<'a, 't> {
     block: Block<'a>,
     canvas: &'t AppCanvas,
+    viewport: Viewport,
+    /// show both halves of each cell using half block characters, instead of only one color per cell
+    half_blocks: bool,
+    colors: ColorSupport,
+    /// the selected tool and brush size, highlighted in the toolbar
+    tool: Tool,
+    brush_size: u16,
 }
 
 impl<'a, 't> CanvasWidget<'a, 't> {
-    pub fn new(canvas: &'t AppCanvas, block: Block<'a>) -> CanvasWidget<'a, 't> {
-        CanvasWidget { block, canvas }
+    pub fn new(
+        canvas: &'t AppCanvas,
+        viewport: Viewport,
+        half_blocks: bool,
+        colors: ColorSupport,
+        tool: Tool,
+        brush_size: u16,
+        block: Block<'a>,
+    ) -> CanvasWidget<'a, 't> {
+        CanvasWidget {
+            block,
+            canvas,
+            viewport,
+            half_blocks,
+            colors,
+            tool,
+            brush_size,
+        }
     }
 }
 
+/// draw a toolbar button, highlighted if it's selected
+fn draw_toolbar_button(buf: &mut tui::buffer::Buffer, x: u16, label: String, selected: bool) {
+    let style = if selected {
+        Style::default().fg(Color::Black).bg(Color::White)
+    } else {
+        Style::default().fg(Color::White).bg(Color::DarkGray)
+    };
+    buf.set_stringn(
+        x,
+        0,
+        format!(" {} ", label),
+        TOOLBAR_BUTTON_WIDTH as usize,
+        style,
+    );
+}
+
 impl<'a, 't, 'b> Widget for CanvasWidget<'a, 't> {
     fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
         self.block.render(area, buf);
         let area = self.block.inner(area);
 
-        for line in self.canvas.lines.iter() {
-            for cell in line.coords_in() {
-                if cell.within(
-                    &Coord(area.x, area.y),
-                    &Coord(area.x + area.width, area.y + area.height),
-                ) {
-                    buf.get_mut(cell.0, cell.1).set_bg(line.color.into());
+        let preview: HashMap<Coord, Option<CanvasColor>> = self
+            .canvas
+            .preview_lines()
+            .flat_map(|line| {
+                line.coords_in()
+                    .into_iter()
+                    .map(move |cell| (cell, line.color))
+            })
+            .collect();
+        let pixels = self.canvas.history.pixels();
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                if !self.viewport.contains(x, y) {
+                    buf.get_mut(x, y).set_symbol("░").set_fg(Color::DarkGray);
+                    continue;
+                }
+                // when scaled down, a half cell shows any of the colors drawn in the cells it covers
+                let color_in = |half| {
+                    self.viewport.canvas_cells(x, y, half).find_map(|cell| {
+                        preview
+                            .get(&cell)
+                            .copied()
+                            .unwrap_or_else(|| pixels.get(cell))
+                    })
+                };
+                let (top, bottom) = (color_in(0), color_in(1));
+                let cell = buf.get_mut(x, y);
+                if !self.half_blocks {
+                    if let Some(color) = top.or(bottom) {
+                        cell.set_bg(self.colors.color(color));
+                    }
+                    continue;
+                }
+                match (top, bottom) {
+                    (Some(top), Some(bottom)) if top == bottom => {
+                        cell.set_bg(self.colors.color(top));
+                    }
+                    (Some(top), bottom) => {
+                        cell.set_symbol("▀").set_fg(self.colors.color(top));
+                        if let Some(bottom) = bottom {
+                            cell.set_bg(self.colors.color(bottom));
+                        }
+                    }
+                    (None, Some(bottom)) => {
+                        cell.set_symbol("▄").set_fg(self.colors.color(bottom));
+                    }
+                    (None, None) => {}
                 }
             }
         }
-        let swatch_size = area.width / self.canvas.palette.len() as u16;
+
+        let layout = ToolbarLayout::new(self.canvas, area.width);
         for (idx, col) in self.canvas.palette.iter().enumerate() {
-            for offset in 0..swatch_size {
-                buf.get_mut(offset + (idx as u16 * swatch_size), 0)
-                    .set_bg((*col).into());
+            let (x, y) = layout.swatch_position(idx);
+            for offset in 0..layout.swatch_size {
+                buf.get_mut(x + offset, y).set_bg(self.colors.color(*col));
             }
         }
+        let buttons = self
+            .canvas
+            .tools
+            .iter()
+            .map(|&tool| (tool.label().to_string(), tool == self.tool))
+            .chain(
+                self.canvas
+                    .brush_sizes
+                    .iter()
+                    .map(|&size| (size.to_string(), size == self.brush_size)),
+            )
+            .take(layout.buttons);
+        for (idx, (label, selected)) in buttons.enumerate() {
+            let x = layout.buttons_x + idx as u16 * TOOLBAR_BUTTON_WIDTH;
+            draw_toolbar_button(buf, x, label, selected);
+        }
+    }
+}
+
+/// the area of the word picker, centered within the canvas
+fn word_picker_area(canvas_rect: Rect, choices: &[String]) -> Rect {
+    let longest_choice = choices.iter().map(|word| word.len()).max().unwrap_or(0) as u16;
+    let width = u16::min(u16::max(longest_choice + 6, 30), canvas_rect.width);
+    let height = u16::min(choices.len() as u16 + 2, canvas_rect.height);
+    Rect {
+        x: canvas_rect.x + (canvas_rect.width - width) / 2,
+        y: canvas_rect.y + (canvas_rect.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// overlay listing the words the drawing user can choose from.
+/// the first line inside the border holds the first word, the second line the second word, and so on.
+pub struct WordPickerWidget<'t> {
+    choices: &'t [String],
+}
+
+impl<'t> WordPickerWidget<'t> {
+    pub fn new(choices: &'t [String]) -> WordPickerWidget<'t> {
+        WordPickerWidget { choices }
+    }
+}
+
+impl<'t> Widget for WordPickerWidget<'t> {
+    fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
+        List::new(
+            self.choices
+                .iter()
+                .enumerate()
+                .map(|(idx, word)| Text::raw(format!("{}. {}", idx + 1, word))),
+        )
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Choose a word (press a number or click)"),
+        )
+        .render(area, buf);
     }
 }
 
@@ -178,6 +596,72 @@ impl<'a, 't, 'b> Widget for ChatWidget<'a, 't> {
     }
 }
 
+pub struct LobbyWidget<'a, 't> {
+    block: Block<'a>,
+    state: &'t LobbyState,
+    username: &'t Username,
+    host: Option<&'t Username>,
+}
+
+impl<'a, 't> LobbyWidget<'a, 't> {
+    pub fn new(
+        state: &'t LobbyState,
+        username: &'t Username,
+        host: Option<&'t Username>,
+        block: Block<'a>,
+    ) -> LobbyWidget<'a, 't> {
+        LobbyWidget {
+            block,
+            state,
+            username,
+            host,
+        }
+    }
+}
+
+impl<'a, 't> Widget for LobbyWidget<'a, 't> {
+    fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
+        self.block.render(area, buf);
+        let area = self.block.inner(area);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(0)
+            .constraints([Constraint::Length(1), Constraint::Percentage(100)].as_ref())
+            .split(area);
+
+        let hint = if self.host == Some(self.username) {
+            "Type !start to start the game"
+        } else {
+            "Type !ready when you're ready"
+        };
+        Paragraph::new([Text::raw(hint)].iter()).render(chunks[0], buf);
+
+        List::new(self.state.players.iter().map(|(username, is_ready)| {
+            let is_host = self.host == Some(username);
+            Text::styled(
+                format!(
+                    "{}{}{}",
+                    username,
+                    if is_host { " [host]" } else { "" },
+                    if *is_ready { " [ready]" } else { "" }
+                ),
+                if *is_ready || is_host {
+                    Style::default().fg(Color::Green)
+                } else {
+                    Style::default()
+                },
+            )
+        }))
+        .block(Block::default().borders(Borders::ALL).title(&format!(
+            "Lobby [{}/{} players]",
+            self.state.players.len(),
+            self.state.min_players
+        )))
+        .render(chunks[1], buf);
+    }
+}
+
 pub struct SkribblStateWidget<'a, 't> {
     block: Block<'a>,
     state: &'t SkribblState,
@@ -219,13 +703,18 @@ impl<'a, 't, 'b> Widget for SkribblStateWidget<'a, 't> {
             self.state.hinted_current_word().to_string()
         };
 
+        let status = if self.state.is_choosing_word() {
+            format!("{} is choosing a word", self.state.drawing_user)
+        } else {
+            format!(
+                "{} drawing {}",
+                self.state.drawing_user, current_word_representation
+            )
+        };
+
         Paragraph::new(
             [Text::Styled(
-                format!(
-                    "{} drawing {}",
-                    self.state.drawing_user, current_word_representation
-                )
-                .into(),
+                status.into(),
                 if is_drawing {
                     Style::default().bg(Color::Red)
                 } else {
@@ -259,11 +748,82 @@ impl<'a, 't, 'b> Widget for SkribblStateWidget<'a, 't> {
                     )
                 }),
         )
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title(&format!("Players [time: {}]", self.remaining_time)),
-        )
+        .block(Block::default().borders(Borders::ALL).title(&format!(
+            "Players [round: {}/{}, time: {}]",
+            self.state.round, self.state.settings.number_of_rounds, self.remaining_time
+        )))
         .render(chunks[1], buf);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Canvas, BRUSH_SIZES};
+
+    fn toolbar_canvas() -> AppCanvas {
+        let mut canvas = AppCanvas::view_only(Canvas::new((10, 10)));
+        canvas.palette = vec![CanvasColor::Red; 32];
+        canvas.tools = vec![Tool::Brush; 7];
+        canvas.brush_sizes = BRUSH_SIZES.to_vec();
+        canvas
+    }
+
+    /// every item of the toolbar, together with where it can be clicked
+    fn clickable_items(canvas: &AppCanvas, width: u16) -> HashMap<ToolbarItem, (u16, u16)> {
+        let mut items = HashMap::new();
+        for y in 0..toolbar_rows(canvas, width) {
+            for x in 0..width {
+                if let Some(item) = toolbar_item_at(canvas, width, x, y) {
+                    items.entry(item).or_insert((x, y));
+                }
+            }
+        }
+        items
+    }
+
+    #[test]
+    fn a_wide_toolbar_has_a_single_row() {
+        let canvas = toolbar_canvas();
+        for width in [80, 120] {
+            assert_eq!(toolbar_rows(&canvas, width), 1);
+            assert_eq!(clickable_items(&canvas, width).len(), 32 + 7 + 4);
+        }
+    }
+
+    #[test]
+    fn the_palette_wraps_instead_of_disappearing() {
+        let canvas = toolbar_canvas();
+        for width in [36, 50, 65] {
+            assert_eq!(toolbar_rows(&canvas, width), 2);
+            let items = clickable_items(&canvas, width);
+            assert_eq!(items.len(), 32 + 7 + 4, "at width {}", width);
+            assert_eq!(items[&ToolbarItem::Color(0)].1, 0);
+            assert_eq!(items[&ToolbarItem::Color(31)].1, 1);
+        }
+    }
+
+    #[test]
+    fn buttons_that_dont_fit_are_left_out() {
+        let canvas = toolbar_canvas();
+        let layout = ToolbarLayout::new(&canvas, 20);
+        assert_eq!(layout.buttons, 6);
+        assert!(layout.buttons_x + layout.buttons as u16 * TOOLBAR_BUTTON_WIDTH <= 20);
+        assert_eq!(toolbar_rows(&canvas, 20), 3);
+        assert_eq!(clickable_items(&canvas, 20).len(), 32 + 6);
+    }
+
+    #[test]
+    fn dragging_reaches_both_halves_of_a_cell() {
+        let viewport = Viewport::new(Rect::new(0, 0, 10, 5), (10, 10));
+        assert_eq!(viewport.canvas_coord(3, 2), Coord(3, 4));
+        // coming from above or below
+        assert_eq!(viewport.drag_coord(3, 2, Coord(3, 2)), Coord(3, 4));
+        assert_eq!(viewport.drag_coord(3, 2, Coord(3, 7)), Coord(3, 5));
+        // moving sideways keeps the half
+        assert_eq!(viewport.drag_coord(4, 2, Coord(3, 5)), Coord(4, 5));
+        assert_eq!(viewport.drag_coord(4, 2, Coord(3, 4)), Coord(4, 4));
+        // the bottom half of the last line is the last row of the canvas
+        assert_eq!(viewport.drag_coord(0, 9, Coord(0, 9)), Coord(0, 9));
+    }
+}
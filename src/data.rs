@@ -1,10 +1,37 @@
-use serde::{Deserialize, Serialize};
-use std::{cmp::Ordering, fmt::Display};
+use crate::message::JoinRejectReason;
+use serde::{Deserialize, Serialize, Serializer};
+use std::{cmp::Ordering, convert::TryFrom, fmt::Display, str::FromStr};
 use tui::style::Color;
 
 #[derive(Eq, PartialEq, Hash, Debug, Clone, Serialize, Deserialize, Ord, PartialOrd)]
 pub struct Username(String);
 
+pub const MAX_USERNAME_LENGTH: usize = 20;
+
+impl Username {
+    /// check that this is a name people can be called by:
+    /// not empty, not too long and only made up of letters, digits, `-` and `_`.
+    pub fn validate(&self) -> Result<(), JoinRejectReason> {
+        let invalid = |reason: String| Err(JoinRejectReason::InvalidUsername(reason));
+        if self.0.is_empty() {
+            invalid("username can't be empty".to_string())
+        } else if self.0.chars().count() > MAX_USERNAME_LENGTH {
+            invalid(format!(
+                "username can't be longer than {} characters",
+                MAX_USERNAME_LENGTH
+            ))
+        } else if let Some(c) = self
+            .0
+            .chars()
+            .find(|c| !c.is_alphanumeric() && *c != '-' && *c != '_')
+        {
+            invalid(format!("username can't contain '{}'", c))
+        } else {
+            Ok(())
+        }
+    }
+}
+
 impl From<String> for Username {
     fn from(s: String) -> Self {
         Username(s)
@@ -23,7 +50,7 @@ impl Display for Username {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, Hash, Serialize, Deserialize)]
 pub struct Coord(pub u16, pub u16);
 
 impl PartialOrd for Coord {
@@ -59,17 +86,404 @@ impl From<Coord> for (i16, i16) {
 pub struct Line {
     pub start: Coord,
     pub end: Coord,
-    pub color: CanvasColor,
+    /// the color to paint with, or None to erase the cells back to the background
+    pub color: Option<CanvasColor>,
+    /// width of the brush in cells, see `brush_offsets`
+    pub width: u16,
 }
 
 impl Line {
-    pub fn new(start: Coord, end: Coord, color: CanvasColor) -> Self {
-        Line { start, end, color }
+    pub fn new(start: Coord, end: Coord, color: Option<CanvasColor>, width: u16) -> Self {
+        Line {
+            start,
+            end,
+            color,
+            width,
+        }
     }
+
+    /// the cells covered by stamping the brush along this line
     pub fn coords_in(&self) -> Vec<Coord> {
-        line_drawing::Bresenham::new(self.start.into(), self.end.into())
+        let brush = brush_offsets(self.width);
+        let mut coords = line_drawing::Bresenham::new(self.start.into(), self.end.into())
+            .flat_map(|(x, y): (i16, i16)| brush.iter().map(move |(dx, dy)| (x + dx, y + dy)))
+            .filter(|(x, y)| *x >= 0 && *y >= 0)
             .map(Coord::from)
-            .collect()
+            .collect::<Vec<_>>();
+        coords.sort_unstable_by_key(|coord| (coord.0, coord.1));
+        coords.dedup();
+        coords
+    }
+}
+
+/// the brush widths that can be picked in the toolbar. wider strokes are rejected by the server.
+pub const BRUSH_SIZES: [u16; 4] = [1, 3, 5, 7];
+
+/// the cells covered by a round brush of the given width, relative to its center.
+/// even widths are rounded down to the next odd one, so the brush has a center cell.
+pub fn brush_offsets(width: u16) -> Vec<(i16, i16)> {
+    let radius = (width.max(1) as i32 - 1) / 2;
+    let mut offsets = Vec::new();
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            // slightly rounded up to avoid spiky edges
+            if dx * dx + dy * dy <= radius * radius + radius {
+                offsets.push((dx as i16, dy as i16));
+            }
+        }
+    }
+    offsets
+}
+
+/// a batch of points of one continuous stroke, connected by lines.
+/// a stroke is sent in several batches while it's being drawn,
+/// each one starting at the point the previous batch ended at.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrokeBatch {
+    /// the stroke these points belong to
+    pub stroke_id: u64,
+    /// the color to paint with, or None for the eraser
+    pub color: Option<CanvasColor>,
+    pub width: u16,
+    pub points: Vec<Coord>,
+}
+
+impl StrokeBatch {
+    pub fn new(stroke_id: u64, color: Option<CanvasColor>, width: u16, start: Coord) -> Self {
+        StrokeBatch {
+            stroke_id,
+            color,
+            width,
+            points: vec![start],
+        }
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = Line> + '_ {
+        self.points
+            .windows(2)
+            .map(move |points| Line::new(points[0], points[1], self.color, self.width))
+    }
+
+    /// append the next batch of the same stroke to this one, if it continues where this one ends
+    /// and the merged batch has at most `MAX_MERGED_POINTS` points
+    pub fn try_merge(&mut self, next: StrokeBatch) -> Result<(), StrokeBatch> {
+        if next.stroke_id == self.stroke_id
+            && next.points.first() == self.points.last()
+            && self.points.len() + next.points.len() - 1 <= MAX_MERGED_POINTS
+        {
+            self.points.extend(next.points.into_iter().skip(1));
+            Ok(())
+        } else {
+            Err(next)
+        }
+    }
+}
+
+/// fill the contiguous area of same colored cells around `origin` with `color`
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Fill {
+    pub id: u64,
+    pub origin: Coord,
+    pub color: CanvasColor,
+}
+
+/// an image pasted onto the whole canvas. cells without a color in it keep what was drawn below.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Image {
+    pub id: u64,
+    pub pixels: Canvas,
+}
+
+/// a single change to the canvas.
+/// applying the same operations in the same order always results in the same pixels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DrawOp {
+    Stroke(StrokeBatch),
+    Fill(Fill),
+    Image(Image),
+}
+
+impl DrawOp {
+    /// the id of the stroke or fill, which is used to undo it
+    pub fn id(&self) -> u64 {
+        match self {
+            DrawOp::Stroke(stroke) => stroke.stroke_id,
+            DrawOp::Fill(fill) => fill.id,
+            DrawOp::Image(image) => image.id,
+        }
+    }
+
+    /// whether all points of the operation lie on a canvas of the given size,
+    /// and strokes are drawn with one of the brush sizes
+    pub fn fits(&self, dimensions: (usize, usize)) -> bool {
+        let fits =
+            |coord: &Coord| (coord.0 as usize) < dimensions.0 && (coord.1 as usize) < dimensions.1;
+        let max_width = BRUSH_SIZES.iter().copied().max().unwrap_or(1);
+        match self {
+            DrawOp::Stroke(stroke) => {
+                (1..=max_width).contains(&stroke.width) && stroke.points.iter().all(fits)
+            }
+            DrawOp::Fill(fill) => fits(&fill.origin),
+            DrawOp::Image(image) => image.pixels.dimensions() == dimensions,
+        }
+    }
+}
+
+/// add an operation to a list of operations, merging it into the last one if it continues that stroke
+pub fn push_op(ops: &mut Vec<DrawOp>, op: DrawOp) {
+    let op = match (ops.last_mut(), op) {
+        (Some(DrawOp::Stroke(last)), DrawOp::Stroke(batch)) => match last.try_merge(batch) {
+            Ok(()) => return,
+            Err(batch) => DrawOp::Stroke(batch),
+        },
+        (_, op) => op,
+    };
+    ops.push(op);
+}
+
+/// how many operations can be undone. older ones are baked into the canvas they were drawn on.
+pub const UNDO_HISTORY_LENGTH: usize = 64;
+
+/// the most points batches of a stroke are merged into. longer strokes are kept as several operations,
+/// so a single long drag can't make the undo history grow without bound.
+pub const MAX_MERGED_POINTS: usize = 1024;
+
+/// the most cells a canvas can have, so a canvas received from someone else can't use up all memory
+pub const MAX_CANVAS_CELLS: usize = 1 << 22;
+
+/// the pixels of a canvas, the result of applying draw operations to an empty canvas.
+/// terminal cells are about twice as high as they are wide, so each canvas cell is half of a terminal cell,
+/// which makes them roughly square.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "run_length::EncodedCanvas")]
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    #[serde(serialize_with = "run_length::serialize")]
+    cells: Vec<Option<CanvasColor>>,
+}
+
+/// most of a canvas is usually empty or covered by large areas of the same color,
+/// so cells are sent as runs of the same color
+mod run_length {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        cells: &[Option<CanvasColor>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut runs: Vec<(u32, Option<CanvasColor>)> = Vec::new();
+        for cell in cells {
+            match runs.last_mut() {
+                Some((count, color)) if color == cell => *count += 1,
+                _ => runs.push((1, *cell)),
+            }
+        }
+        runs.serialize(serializer)
+    }
+
+    /// a canvas the way it's sent, before checking that its runs add up to its size
+    #[derive(Deserialize)]
+    pub struct EncodedCanvas {
+        width: usize,
+        height: usize,
+        cells: Vec<(u32, Option<CanvasColor>)>,
+    }
+
+    impl TryFrom<EncodedCanvas> for Canvas {
+        type Error = String;
+
+        fn try_from(encoded: EncodedCanvas) -> Result<Self, Self::Error> {
+            let len = match encoded.width.checked_mul(encoded.height) {
+                Some(len) if len <= MAX_CANVAS_CELLS => len,
+                _ => return Err("the canvas is too large".to_string()),
+            };
+            let mut cells = Vec::with_capacity(len);
+            for (count, color) in encoded.cells {
+                if count as usize > len - cells.len() {
+                    return Err("the canvas has more cells than fit into it".to_string());
+                }
+                cells.extend((0..count).map(|_| color));
+            }
+            if cells.len() != len {
+                return Err("the canvas has fewer cells than fit into it".to_string());
+            }
+            Ok(Canvas {
+                width: encoded.width,
+                height: encoded.height,
+                cells,
+            })
+        }
+    }
+}
+
+impl Canvas {
+    pub fn new(dimensions: (usize, usize)) -> Self {
+        Canvas {
+            width: dimensions.0,
+            height: dimensions.1,
+            cells: vec![None; dimensions.0 * dimensions.1],
+        }
+    }
+
+    pub fn from_ops<'a>(
+        dimensions: (usize, usize),
+        ops: impl IntoIterator<Item = &'a DrawOp>,
+    ) -> Self {
+        let mut canvas = Canvas::new(dimensions);
+        for op in ops {
+            canvas.apply(op);
+        }
+        canvas
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn index(&self, coord: Coord) -> Option<usize> {
+        let (x, y) = (coord.0 as usize, coord.1 as usize);
+        if x < self.width && y < self.height {
+            Some(y * self.width + x)
+        } else {
+            None
+        }
+    }
+
+    /// the color of the given cell, or None if nothing was drawn there
+    pub fn get(&self, coord: Coord) -> Option<CanvasColor> {
+        self.index(coord).and_then(|idx| self.cells[idx])
+    }
+
+    pub fn set(&mut self, coord: Coord, color: Option<CanvasColor>) {
+        if let Some(idx) = self.index(coord) {
+            self.cells[idx] = color;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.iter_mut().for_each(|cell| *cell = None);
+    }
+
+    /// whether nothing is drawn on the canvas
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(Option::is_none)
+    }
+
+    pub fn apply(&mut self, op: &DrawOp) {
+        match op {
+            DrawOp::Stroke(stroke) => {
+                for line in stroke.lines() {
+                    for coord in line.coords_in() {
+                        self.set(coord, line.color);
+                    }
+                }
+            }
+            DrawOp::Fill(fill) => self.fill(fill.origin, fill.color),
+            DrawOp::Image(image) => {
+                for (cell, pixel) in self.cells.iter_mut().zip(&image.pixels.cells) {
+                    if pixel.is_some() {
+                        *cell = *pixel;
+                    }
+                }
+            }
+        }
+    }
+
+    /// flood fill the 4-connected area of cells that have the same color as `origin`
+    fn fill(&mut self, origin: Coord, color: CanvasColor) {
+        let target = match self.index(origin) {
+            Some(idx) => self.cells[idx],
+            None => return,
+        };
+        if target == Some(color) {
+            return;
+        }
+        let mut todo = vec![origin];
+        while let Some(coord) = todo.pop() {
+            match self.index(coord) {
+                Some(idx) if self.cells[idx] == target => self.cells[idx] = Some(color),
+                _ => continue,
+            }
+            let Coord(x, y) = coord;
+            todo.push(Coord(x + 1, y));
+            todo.push(Coord(x, y + 1));
+            if x > 0 {
+                todo.push(Coord(x - 1, y));
+            }
+            if y > 0 {
+                todo.push(Coord(x, y - 1));
+            }
+        }
+    }
+}
+
+/// a canvas together with the most recent operations drawn on it, which can still be undone.
+/// once there are more than `UNDO_HISTORY_LENGTH` of them, the oldest ones are applied to the base canvas
+/// and forgotten, so the history stays bounded no matter how much is drawn.
+#[derive(Debug, Clone)]
+pub struct History {
+    /// the pixels of everything that can't be undone anymore
+    base: Canvas,
+    /// the operations that can still be undone, in order
+    ops: Vec<DrawOp>,
+    /// the pixels of `base` with `ops` applied
+    pixels: Canvas,
+}
+
+impl History {
+    pub fn new(base: Canvas, ops: Vec<DrawOp>) -> Self {
+        let mut pixels = base.clone();
+        for op in ops.iter() {
+            pixels.apply(op);
+        }
+        History { base, ops, pixels }
+    }
+
+    /// the current pixels of the canvas
+    pub fn pixels(&self) -> &Canvas {
+        &self.pixels
+    }
+
+    /// the canvas before any of the operations that can still be undone
+    pub fn base(&self) -> &Canvas {
+        &self.base
+    }
+
+    pub fn ops(&self) -> &[DrawOp] {
+        &self.ops
+    }
+
+    pub fn apply(&mut self, op: DrawOp) {
+        self.pixels.apply(&op);
+        push_op(&mut self.ops, op);
+        if self.ops.len() > UNDO_HISTORY_LENGTH {
+            let forgotten = self.ops.len() - UNDO_HISTORY_LENGTH;
+            for op in self.ops.drain(..forgotten) {
+                self.base.apply(&op);
+            }
+        }
+    }
+
+    /// the id of the most recent stroke or fill
+    pub fn last_id(&self) -> Option<u64> {
+        self.ops.last().map(DrawOp::id)
+    }
+
+    /// remove the stroke or fill with the given id, returning its operations.
+    /// parts of it that were already applied to the base canvas stay.
+    pub fn remove(&mut self, id: u64) -> Vec<DrawOp> {
+        let (removed, kept) = std::mem::take(&mut self.ops)
+            .into_iter()
+            .partition(|op| op.id() == id);
+        *self = History::new(self.base.clone(), kept);
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        self.base.clear();
+        self.ops.clear();
+        self.pixels.clear();
     }
 }
 
@@ -111,7 +525,7 @@ impl Display for Message {
     }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CanvasColor {
     White,
     Gray,
@@ -129,6 +543,137 @@ pub enum CanvasColor {
     LightCyan,
     Magenta,
     LightMagenta,
+    /// a color of the 256 color palette
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl CanvasColor {
+    /// the 16 colors every terminal supports, in the order they appear in the 256 color palette
+    pub const ANSI: [CanvasColor; 16] = [
+        CanvasColor::Black,
+        CanvasColor::Red,
+        CanvasColor::Green,
+        CanvasColor::Yellow,
+        CanvasColor::Blue,
+        CanvasColor::Magenta,
+        CanvasColor::Cyan,
+        CanvasColor::Gray,
+        CanvasColor::DarkGray,
+        CanvasColor::LightRed,
+        CanvasColor::LightGreen,
+        CanvasColor::LightYellow,
+        CanvasColor::LightBlue,
+        CanvasColor::LightMagenta,
+        CanvasColor::LightCyan,
+        CanvasColor::White,
+    ];
+
+    /// the red, green and blue parts of this color, as most terminals show it
+    pub fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            CanvasColor::Rgb(r, g, b) => (r, g, b),
+            CanvasColor::Indexed(idx) => indexed_rgb(idx),
+            named => {
+                let idx = CanvasColor::ANSI.iter().position(|&c| c == named);
+                indexed_rgb(idx.unwrap_or(0) as u8)
+            }
+        }
+    }
+
+    /// the closest of the 16 colors every terminal supports
+    pub fn to_ansi(self) -> CanvasColor {
+        match self {
+            CanvasColor::Indexed(idx) if idx < 16 => CanvasColor::ANSI[idx as usize],
+            CanvasColor::Indexed(_) | CanvasColor::Rgb(..) => {
+                CanvasColor::ANSI[self.nearest_index(0..16) as usize]
+            }
+            named => named,
+        }
+    }
+
+    /// the closest color of the 256 color palette
+    pub fn to_indexed(self) -> CanvasColor {
+        match self {
+            // the first 16 colors are often changed by color schemes, so only pick from the rest
+            CanvasColor::Rgb(..) => CanvasColor::Indexed(self.nearest_index(16..=255)),
+            other => other,
+        }
+    }
+
+    /// the index of the color in the 256 color palette that is closest to this one
+    fn nearest_index(self, candidates: impl Iterator<Item = u8>) -> u8 {
+        let (r, g, b) = self.rgb();
+        let distance = |idx: &u8| {
+            let (r2, g2, b2) = indexed_rgb(*idx);
+            let (dr, dg, db) = (
+                r as i32 - r2 as i32,
+                g as i32 - g2 as i32,
+                b as i32 - b2 as i32,
+            );
+            dr * dr + dg * dg + db * db
+        };
+        candidates.min_by_key(distance).unwrap_or(0)
+    }
+}
+
+/// the red, green and blue parts of a color of the 256 color palette, as xterm shows them
+fn indexed_rgb(idx: u8) -> (u8, u8, u8) {
+    const ANSI_RGB: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match idx {
+        0..=15 => ANSI_RGB[idx as usize],
+        16..=231 => {
+            let idx = idx - 16;
+            (
+                CUBE_LEVELS[(idx / 36) as usize],
+                CUBE_LEVELS[(idx / 6 % 6) as usize],
+                CUBE_LEVELS[(idx % 6) as usize],
+            )
+        }
+        _ => {
+            let gray = 8 + (idx - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// parse a color like `#ff8800` or an index into the 256 color palette like `208`
+impl FromStr for CanvasColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("\"{}\" is not a color like #ff8800 or 0 to 255", s);
+        if let Some(hex) = s.strip_prefix('#') {
+            let part = |range: std::ops::Range<usize>| {
+                hex.get(range)
+                    .and_then(|part| u8::from_str_radix(part, 16).ok())
+            };
+            match (hex.len(), part(0..2), part(2..4), part(4..6)) {
+                (6, Some(r), Some(g), Some(b)) => Ok(CanvasColor::Rgb(r, g, b)),
+                _ => Err(invalid()),
+            }
+        } else {
+            s.parse().map(CanvasColor::Indexed).map_err(|_| invalid())
+        }
+    }
 }
 
 impl From<CanvasColor> for Color {
@@ -150,6 +695,8 @@ impl From<CanvasColor> for Color {
             CanvasColor::LightCyan => Color::LightCyan,
             CanvasColor::Magenta => Color::Magenta,
             CanvasColor::LightMagenta => Color::LightMagenta,
+            CanvasColor::Indexed(idx) => Color::Indexed(idx),
+            CanvasColor::Rgb(r, g, b) => Color::Rgb(r, g, b),
         }
     }
 }
@@ -157,4 +704,138 @@ impl From<CanvasColor> for Color {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CommandMsg {
     KickPlayer(Username),
+    /// kick a player and don't let them join again
+    BanPlayer(Username),
+    /// hide all chat messages of a player
+    MutePlayer(Username),
+    UnmutePlayer(Username),
+    /// end the current turn without waiting for everyone to guess
+    SkipTurn,
+    /// start a new game with everyone that's currently connected
+    RestartGame,
+    /// toggle whether we're ready to start the game
+    ToggleReady,
+    /// start the game, if everyone is ready
+    StartGame,
+}
+
+impl CommandMsg {
+    /// parse a command typed into the chat, like `!kick <username>`.
+    /// returns None if the input isn't a known command.
+    pub fn parse(input: &str) -> Option<CommandMsg> {
+        let mut words = input.trim().splitn(2, ' ');
+        let command = words.next()?;
+        let argument = words
+            .next()
+            .map(|arg| Username::from(arg.trim().to_string()));
+
+        match (command, argument) {
+            ("!kick", Some(username)) => Some(CommandMsg::KickPlayer(username)),
+            ("!ban", Some(username)) => Some(CommandMsg::BanPlayer(username)),
+            ("!mute", Some(username)) => Some(CommandMsg::MutePlayer(username)),
+            ("!unmute", Some(username)) => Some(CommandMsg::UnmutePlayer(username)),
+            ("!skip", None) => Some(CommandMsg::SkipTurn),
+            ("!restart", None) => Some(CommandMsg::RestartGame),
+            ("!ready", None) => Some(CommandMsg::ToggleReady),
+            ("!start", None) => Some(CommandMsg::StartGame),
+            _ => None,
+        }
+    }
+
+    /// whether only the host of a room may use this command
+    pub fn requires_host(&self) -> bool {
+        !matches!(self, CommandMsg::ToggleReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(json: &str) -> Result<Canvas, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn brush_offsets_dont_overflow() {
+        assert_eq!(brush_offsets(1), vec![(0, 0)]);
+        assert_eq!(brush_offsets(3).len(), 9);
+        assert!(!brush_offsets(400).is_empty());
+    }
+
+    #[test]
+    fn strokes_wider_than_the_largest_brush_dont_fit() {
+        let stroke = |width| DrawOp::Stroke(StrokeBatch::new(1, None, width, Coord(1, 1)));
+        assert!(stroke(7).fits((10, 10)));
+        assert!(!stroke(0).fits((10, 10)));
+        assert!(!stroke(8).fits((10, 10)));
+        assert!(!stroke(u16::MAX).fits((10, 10)));
+    }
+
+    #[test]
+    fn a_long_drag_keeps_the_history_bounded() {
+        let mut history = History::new(Canvas::new((100, 100)), Vec::new());
+        let mut last = Coord(0, 0);
+        for idx in 1..100_000u32 {
+            let next = Coord((idx % 100) as u16, (idx / 100 % 100) as u16);
+            let mut batch = StrokeBatch::new(1, Some(CanvasColor::Red), 1, last);
+            batch.points.push(next);
+            history.apply(DrawOp::Stroke(batch));
+            last = next;
+        }
+        let points = history
+            .ops()
+            .iter()
+            .map(|op| match op {
+                DrawOp::Stroke(stroke) => stroke.points.len(),
+                _ => 0,
+            })
+            .sum::<usize>();
+        assert!(history.ops().len() <= UNDO_HISTORY_LENGTH);
+        assert!(points <= UNDO_HISTORY_LENGTH * MAX_MERGED_POINTS);
+
+        // whatever is left of the stroke is still undone together
+        assert_eq!(
+            history.remove(1).len(),
+            history.ops().len() + UNDO_HISTORY_LENGTH
+        );
+        assert!(history.ops().is_empty());
+    }
+
+    #[test]
+    fn canvas_round_trips_through_runs() {
+        let mut canvas = Canvas::new((5, 3));
+        canvas.set(Coord(1, 0), Some(CanvasColor::Red));
+        canvas.set(Coord(2, 0), Some(CanvasColor::Red));
+        canvas.set(Coord(4, 2), Some(CanvasColor::Rgb(1, 2, 3)));
+
+        let json = serde_json::to_string(&canvas).unwrap();
+        let decoded = decode(&json).unwrap();
+        assert_eq!(decoded.dimensions(), (5, 3));
+        assert_eq!(decoded.cells, canvas.cells);
+
+        let bytes = bincode::serialize(&canvas).unwrap();
+        let decoded: Canvas = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.cells, canvas.cells);
+    }
+
+    #[test]
+    fn canvas_with_too_many_cells_is_rejected() {
+        assert!(decode(r#"{"width":2,"height":2,"cells":[[5,null]]}"#).is_err());
+        assert!(decode(r#"{"width":2,"height":2,"cells":[[4294967295,"Red"]]}"#).is_err());
+        assert!(decode(r#"{"width":2,"height":2,"cells":[[3,null],[4294967295,null]]}"#).is_err());
+    }
+
+    #[test]
+    fn canvas_with_too_few_cells_is_rejected() {
+        assert!(decode(r#"{"width":2,"height":2,"cells":[[3,null]]}"#).is_err());
+        assert!(decode(r#"{"width":2,"height":2,"cells":[]}"#).is_err());
+    }
+
+    #[test]
+    fn huge_canvas_is_rejected() {
+        assert!(decode(r#"{"width":65536,"height":65536,"cells":[[4294967295,null]]}"#).is_err());
+        let overflowing = format!(r#"{{"width":{},"height":2,"cells":[]}}"#, usize::MAX);
+        assert!(decode(&overflowing).is_err());
+    }
 }
@@ -0,0 +1,204 @@
+use crate::data::{Canvas, CanvasColor, Coord, DrawOp, History};
+use std::{fmt::Write as _, path::Path};
+
+/// how many pixels wide and high a single canvas cell is in exported images, unless asked otherwise
+pub const DEFAULT_SCALE: u32 = 8;
+
+/// the largest scale drawings can be exported with, larger ones would quickly take up gigabytes
+pub const MAX_SCALE: u32 = 64;
+
+/// the color of cells nothing was drawn on, like the background of most terminals
+const BACKGROUND: (u8, u8, u8) = (0, 0, 0);
+
+/// save the canvas to the given file, as png or svg depending on its extension
+pub fn save(history: &History, path: &Path, scale: u32) -> Result<(), String> {
+    if !(1..=MAX_SCALE).contains(&scale) {
+        return Err(format!("the scale has to be from 1 to {}", MAX_SCALE));
+    }
+    let contents = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("png") => png(history.pixels(), scale)?,
+        Some("svg") => svg(history, scale).into_bytes(),
+        _ => return Err("drawings can only be saved as .png or .svg".to_string()),
+    };
+    std::fs::write(path, contents).map_err(|err| err.to_string())
+}
+
+/// render the pixels of the canvas to a png, each cell becoming a square of `scale` by `scale` pixels
+pub fn png(canvas: &Canvas, scale: u32) -> Result<Vec<u8>, String> {
+    let scale = scale.clamp(1, MAX_SCALE) as usize;
+    let (width, height) = canvas.dimensions();
+    let mut data = Vec::with_capacity(width * height * scale * scale * 3);
+    for y in 0..height {
+        let row_start = data.len();
+        for x in 0..width {
+            let (r, g, b) = cell_rgb(canvas.get(Coord(x as u16, y as u16)));
+            for _ in 0..scale {
+                data.extend_from_slice(&[r, g, b]);
+            }
+        }
+        let row_end = data.len();
+        for _ in 1..scale {
+            data.extend_from_within(row_start..row_end);
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+    writer
+        .write_image_data(&data)
+        .map_err(|err| err.to_string())?;
+    writer.finish().map_err(|err| err.to_string())?;
+    Ok(png)
+}
+
+/// render the canvas to an svg, with one path per stroke.
+/// cells are one unit in size, the whole image is scaled to `scale` pixels per cell.
+pub fn svg(history: &History, scale: u32) -> String {
+    let scale = u64::from(scale.clamp(1, MAX_SCALE));
+    let (width, height) = history.base().dimensions();
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+        width as u64 * scale,
+        height as u64 * scale,
+        width,
+        height
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="100%" height="100%" fill="{}"/>"#,
+        hex(BACKGROUND)
+    );
+
+    // whatever can't be undone anymore only exists as pixels
+    let mut canvas = Canvas::new((width, height));
+    write_changed_cells(&mut svg, &canvas, history.base());
+    canvas = history.base().clone();
+
+    for op in history.ops() {
+        match op {
+            DrawOp::Stroke(stroke) => {
+                let points = stroke
+                    .points
+                    .iter()
+                    .map(|Coord(x, y)| format!("{}.5 {}.5", x, y))
+                    .collect::<Vec<_>>();
+                let _ = writeln!(
+                    svg,
+                    r#"<path d="M {}" fill="none" stroke="{}" stroke-width="{}" stroke-linecap="round" stroke-linejoin="round"/>"#,
+                    points.join(" L "),
+                    hex(cell_rgb(stroke.color)),
+                    stroke.width.max(1)
+                );
+                canvas.apply(op);
+            }
+            // fills and images depend on what was drawn before, so they're stored as the cells they changed
+            DrawOp::Fill(_) | DrawOp::Image(_) => {
+                let before = canvas.clone();
+                canvas.apply(op);
+                write_changed_cells(&mut svg, &before, &canvas);
+            }
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// write the cells that differ between two canvases as rectangles, merging neighbours of the same color
+fn write_changed_cells(svg: &mut String, before: &Canvas, after: &Canvas) {
+    let (width, height) = after.dimensions();
+    for y in 0..height {
+        let mut x = 0;
+        while x < width {
+            let coord = |x: usize| Coord(x as u16, y as u16);
+            let color = after.get(coord(x));
+            if color == before.get(coord(x)) {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < width && after.get(coord(x)) == color && before.get(coord(x)) != color {
+                x += 1;
+            }
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{}" y="{}" width="{}" height="1" fill="{}"/>"#,
+                start,
+                y,
+                x - start,
+                hex(cell_rgb(color))
+            );
+        }
+    }
+}
+
+/// replace everything but letters, digits and `-` with `_`, so the name can be used for a file
+pub fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn cell_rgb(color: Option<CanvasColor>) -> (u8, u8, u8) {
+    color.map_or(BACKGROUND, CanvasColor::rgb)
+}
+
+fn hex((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::StrokeBatch;
+
+    fn history() -> History {
+        let mut stroke = StrokeBatch::new(1, Some(CanvasColor::Red), 1, Coord(1, 1));
+        stroke.points.push(Coord(4, 2));
+        History::new(Canvas::new((6, 4)), vec![DrawOp::Stroke(stroke)])
+    }
+
+    #[test]
+    fn png_scales_up_every_cell() {
+        let png = png(history().pixels(), 3).unwrap();
+        let bitmap = crate::import::decode(&png).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (18, 12));
+        let canvas = history().pixels().clone();
+        for y in 0..12 {
+            for x in 0..18 {
+                let (r, g, b) = cell_rgb(canvas.get(Coord(x as u16 / 3, y as u16 / 3)));
+                assert_eq!(bitmap.pixels[y * 18 + x], [r, g, b, 255], "at {}, {}", x, y);
+            }
+        }
+        assert_ne!(bitmap.pixels[3 * 18 + 3], [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn svg_draws_strokes_as_paths() {
+        let svg = svg(&history(), 3);
+        assert!(svg.contains(r#"width="18" height="12" viewBox="0 0 6 4""#));
+        assert!(svg.contains(r#"<path d="M 1.5 1.5 L 4.5 2.5""#));
+    }
+
+    #[test]
+    fn scales_are_limited() {
+        let history = History::new(Canvas::new((4, 2)), Vec::new());
+        let path = Path::new("drawing.png");
+        assert!(save(&history, path, 0).is_err());
+        assert!(save(&history, path, MAX_SCALE + 1).is_err());
+
+        let bitmap = crate::import::decode(&png(history.pixels(), u32::MAX).unwrap()).unwrap();
+        assert_eq!(bitmap.width, 4 * MAX_SCALE as usize);
+        assert!(svg(&history, u32::MAX).contains(&format!(r#"width="{}""#, 4 * MAX_SCALE)));
+    }
+}
@@ -0,0 +1,244 @@
+use crate::{
+    client::{
+        app::{AppCanvas, Tool},
+        ui::{self, CanvasWidget, ColorSupport, Viewport},
+    },
+    data::{Canvas, Username},
+    export,
+};
+use argh::FromArgs;
+use crossterm::{
+    event::{read, Event, KeyCode},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{stdout, Write},
+    path::{Path, PathBuf},
+};
+use tui::{
+    backend::CrosstermBackend,
+    style::{Color, Style},
+    widgets::{Paragraph, Text},
+    Terminal,
+};
+
+#[derive(FromArgs)]
+/// look at the drawings a server saved with --gallery-dir
+#[argh(subcommand, name = "gallery")]
+pub struct CliOpts {
+    #[argh(positional)]
+    /// directory the drawings were saved to.
+    pub dir: PathBuf,
+
+    #[argh(switch)]
+    /// draw the canvas with half block characters, see `termibbl client --help`.
+    pub half_blocks: bool,
+}
+
+/// a drawing as it looked at the end of a turn
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub room: String,
+    pub word: String,
+    pub drawer: Username,
+    /// the players that guessed the word
+    pub solved_by: Vec<Username>,
+    /// when the turn ended, in seconds since the unix epoch
+    pub time: u64,
+    pub canvas: Canvas,
+}
+
+impl Snapshot {
+    /// save the snapshot as json into the given directory, returning the path of the new file.
+    /// `number` tells apart snapshots of the same room and word saved in the same second.
+    pub fn save(&self, dir: &Path, number: u64) -> Result<PathBuf, String> {
+        let name = format!("{}-{}-{}-{}", self.time, self.room, number, self.word);
+        let path = dir.join(format!("{}.json", export::file_name(&name)));
+        let json = serde_json::to_vec(self).map_err(|err| err.to_string())?;
+        std::fs::write(&path, json).map_err(|err| err.to_string())?;
+        Ok(path)
+    }
+
+    /// load all snapshots in the given directory, oldest first.
+    /// files that aren't snapshots are skipped.
+    pub fn load_all(dir: &Path) -> std::io::Result<Vec<Snapshot>> {
+        let mut snapshots = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let snapshot = std::fs::read(&path)
+                .ok()
+                .and_then(|json| serde_json::from_slice::<Snapshot>(&json).ok());
+            if let Some(snapshot) = snapshot {
+                snapshots.push(snapshot);
+            }
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.time);
+        Ok(snapshots)
+    }
+}
+
+/// show the saved drawings one at a time, until the user quits
+pub fn run(opt: CliOpts) -> Result<(), String> {
+    let snapshots = Snapshot::load_all(&opt.dir)
+        .map_err(|err| format!("Could not read {}: {}", opt.dir.display(), err))?;
+    if snapshots.is_empty() {
+        return Err(format!("There are no drawings in {}", opt.dir.display()));
+    }
+
+    let result = browse(&snapshots, opt.half_blocks);
+    let _ = execute!(stdout(), LeaveAlternateScreen);
+    let _ = disable_raw_mode();
+    result.map_err(|err| err.to_string())
+}
+
+fn browse(snapshots: &[Snapshot], half_blocks: bool) -> crossterm::Result<()> {
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    let colors = ColorSupport::detect();
+    let mut current = snapshots.len() - 1;
+    loop {
+        let snapshot = &snapshots[current];
+        let canvas = AppCanvas::view_only(snapshot.canvas.clone());
+        let (canvas_rect, sidebar_rect) =
+            ui::canvas_and_sidebar(terminal.size()?, canvas.dimensions);
+        let viewport = Viewport::new(ui::canvas_block().inner(canvas_rect), canvas.dimensions);
+        let solved_by = snapshot
+            .solved_by
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let info = [
+            Text::styled(
+                format!("{}\n\n", snapshot.word),
+                Style::default().fg(Color::Yellow),
+            ),
+            Text::raw(format!("drawn by {}\n", snapshot.drawer)),
+            Text::raw(if solved_by.is_empty() {
+                "nobody guessed it\n".to_string()
+            } else {
+                format!("guessed by {}\n", solved_by.join(", "))
+            }),
+            Text::raw(format!(
+                "\nroom {}\n{}\n\n",
+                snapshot.room,
+                format_time(snapshot.time)
+            )),
+            Text::styled(
+                format!(
+                    "drawing {} of {}\n<- / -> to browse, q to quit",
+                    current + 1,
+                    snapshots.len()
+                ),
+                Style::default().fg(Color::Gray),
+            ),
+        ];
+
+        terminal.draw(|mut f| {
+            let canvas_widget = CanvasWidget::new(
+                &canvas,
+                viewport,
+                half_blocks,
+                colors,
+                Tool::Brush,
+                0,
+                ui::canvas_block(),
+            );
+            f.render_widget(canvas_widget, canvas_rect);
+            f.render_widget(Paragraph::new(info.iter()).wrap(true), sidebar_rect);
+        })?;
+
+        if let Event::Key(key) = read()? {
+            match key.code {
+                KeyCode::Left | KeyCode::Char('h') => current = current.saturating_sub(1),
+                KeyCode::Right | KeyCode::Char('l') => {
+                    current = usize::min(current + 1, snapshots.len() - 1)
+                }
+                KeyCode::Esc | KeyCode::Char('q') => break Ok(()),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// format a unix timestamp as a utc date and time, like `2020-06-14 18:30 UTC`
+fn format_time(time: u64) -> String {
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (time / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let seconds_of_day = time % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{CanvasColor, Coord};
+
+    #[test]
+    fn format_time_shows_the_date_and_time_in_utc() {
+        assert_eq!(format_time(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_time(1_709_210_040), "2024-02-29 12:34 UTC");
+        assert_eq!(format_time(951_868_740), "2000-02-29 23:59 UTC");
+        assert_eq!(format_time(951_868_800), "2000-03-01 00:00 UTC");
+    }
+
+    #[test]
+    fn snapshots_round_trip_through_a_directory() {
+        let dir = std::env::temp_dir().join(format!("termibbl-gallery-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut canvas = Canvas::new((4, 3));
+        canvas.set(Coord(1, 2), Some(CanvasColor::Red));
+        let snapshot = |time, word: &str| Snapshot {
+            room: "room".to_string(),
+            word: word.to_string(),
+            drawer: Username::from("drawer".to_string()),
+            solved_by: vec![Username::from("guesser".to_string())],
+            time,
+            canvas: canvas.clone(),
+        };
+        snapshot(20, "banana").save(&dir, 0).unwrap();
+        snapshot(10, "apple").save(&dir, 0).unwrap();
+        std::fs::write(dir.join("settings.json"), r#"{"volume": 3}"#).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a drawing").unwrap();
+
+        let loaded = Snapshot::load_all(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let loaded = loaded.unwrap();
+
+        let words = loaded.iter().map(|s| s.word.as_str()).collect::<Vec<_>>();
+        assert_eq!(words, ["apple", "banana"]);
+        let first = &loaded[0];
+        assert_eq!((first.room.as_str(), first.time), ("room", 10));
+        assert_eq!(first.drawer, Username::from("drawer".to_string()));
+        assert_eq!(first.solved_by, [Username::from("guesser".to_string())]);
+        assert_eq!(first.canvas.dimensions(), (4, 3));
+        assert_eq!(first.canvas.get(Coord(1, 2)), Some(CanvasColor::Red));
+        assert_eq!(first.canvas.get(Coord(2, 1)), None);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::data::{Canvas, CanvasColor, Coord};
+use std::path::Path;
+
+/// the most pixels an image may have, so huge images can't use up all memory
+const MAX_PIXELS: usize = 1 << 25;
+
+/// the pixels of a decoded image, row by row
+#[derive(Debug, Clone)]
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    /// red, green, blue and alpha of every pixel
+    pub pixels: Vec<[u8; 4]>,
+}
+
+/// decode a png or jpeg file
+pub fn load(path: &Path) -> Result<Bitmap, String> {
+    let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+    decode(&bytes)
+}
+
+/// decode a png or jpeg image, telling them apart by their contents
+pub fn decode(bytes: &[u8]) -> Result<Bitmap, String> {
+    let bitmap = if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+        decode_png(bytes)?
+    } else if bytes.starts_with(&[0xff, 0xd8]) {
+        decode_jpeg(bytes)?
+    } else {
+        return Err("only png and jpeg images can be imported".to_string());
+    };
+    if bitmap.width == 0 || bitmap.height == 0 {
+        return Err("the image is empty".to_string());
+    }
+    Ok(bitmap)
+}
+
+fn decode_png(bytes: &[u8]) -> Result<Bitmap, String> {
+    let mut decoder = png::Decoder::new_with_limits(
+        bytes,
+        png::Limits {
+            bytes: MAX_PIXELS * 8,
+        },
+    );
+    // palettes, transparent colors and 16 bit channels are all turned into 8 bit channels
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let (width, height) = reader.info().size();
+    check_size(width as usize, height as usize)?;
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf).map_err(|err| err.to_string())?;
+    let data = &buf[..frame.buffer_size()];
+    let pixels = match frame.color_type {
+        png::ColorType::Grayscale => data.iter().map(|&v| [v, v, v, 255]).collect(),
+        png::ColorType::GrayscaleAlpha => data
+            .chunks_exact(2)
+            .map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Rgb => data
+            .chunks_exact(3)
+            .map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::Rgba => data
+            .chunks_exact(4)
+            .map(|p| [p[0], p[1], p[2], p[3]])
+            .collect(),
+        png::ColorType::Indexed => return Err("unsupported png color type".to_string()),
+    };
+    Ok(Bitmap {
+        width: frame.width as usize,
+        height: frame.height as usize,
+        pixels,
+    })
+}
+
+fn decode_jpeg(bytes: &[u8]) -> Result<Bitmap, String> {
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    decoder.set_max_decoding_buffer_size(MAX_PIXELS * 4);
+    decoder.read_info().map_err(|err| err.to_string())?;
+    let info = decoder.info().ok_or("the jpeg has no image")?;
+    check_size(info.width as usize, info.height as usize)?;
+
+    let data = decoder.decode().map_err(|err| err.to_string())?;
+    let pixels = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => data.iter().map(|&v| [v, v, v, 255]).collect(),
+        jpeg_decoder::PixelFormat::L16 => data
+            .chunks_exact(2)
+            .map(|p| [p[0], p[0], p[0], 255])
+            .collect(),
+        jpeg_decoder::PixelFormat::RGB24 => data
+            .chunks_exact(3)
+            .map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        jpeg_decoder::PixelFormat::CMYK32 => {
+            return Err("only grayscale and color jpegs are supported".to_string())
+        }
+    };
+    Ok(Bitmap {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+/// scale the image down (or up) to fit the canvas, keeping its aspect ratio and centering it,
+/// and replace every pixel with the closest color of the palette. transparent pixels are left empty.
+pub fn quantize(bitmap: &Bitmap, dimensions: (usize, usize), palette: &[CanvasColor]) -> Canvas {
+    let mut canvas = Canvas::new(dimensions);
+    // how many pixels of the image end up in one cell of the canvas, in each direction
+    let scale = f64::max(
+        bitmap.width as f64 / dimensions.0 as f64,
+        bitmap.height as f64 / dimensions.1 as f64,
+    );
+    let width = usize::min((bitmap.width as f64 / scale).round() as usize, dimensions.0);
+    let height = usize::min(
+        (bitmap.height as f64 / scale).round() as usize,
+        dimensions.1,
+    );
+    let offset = ((dimensions.0 - width) / 2, (dimensions.1 - height) / 2);
+
+    let span = |start: usize, len: usize| {
+        let from = usize::min((start as f64 * scale) as usize, len - 1);
+        let to = usize::max(((start + 1) as f64 * scale) as usize, from + 1);
+        from..to.min(len)
+    };
+    for y in 0..height {
+        for x in 0..width {
+            // the average of all pixels that end up in this cell, weighted by how opaque they are
+            let (mut sum, mut alpha, mut count) = ([0u64; 3], 0u64, 0u64);
+            for py in span(y, bitmap.height) {
+                for px in span(x, bitmap.width) {
+                    let [r, g, b, a] = bitmap.pixels[py * bitmap.width + px];
+                    for (channel, value) in sum.iter_mut().zip(&[r, g, b]) {
+                        *channel += *value as u64 * a as u64;
+                    }
+                    alpha += a as u64;
+                    count += 1;
+                }
+            }
+            if alpha * 2 < count * 255 {
+                continue;
+            }
+            let rgb = (
+                (sum[0] / alpha) as u8,
+                (sum[1] / alpha) as u8,
+                (sum[2] / alpha) as u8,
+            );
+            let coord = Coord((offset.0 + x) as u16, (offset.1 + y) as u16);
+            canvas.set(coord, nearest_color(rgb, palette));
+        }
+    }
+    canvas
+}
+
+fn nearest_color((r, g, b): (u8, u8, u8), palette: &[CanvasColor]) -> Option<CanvasColor> {
+    palette.iter().copied().min_by_key(|color| {
+        let (r2, g2, b2) = color.rgb();
+        let distance = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        distance(r, r2) + distance(g, g2) + distance(b, b2)
+    })
+}
+
+/// make sure an image of the given size can be decoded
+fn check_size(width: usize, height: usize) -> Result<(), String> {
+    match width.checked_mul(height) {
+        Some(pixels) if pixels <= MAX_PIXELS => Ok(()),
+        _ => Err(format!("the image is too large ({}x{})", width, height)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: CanvasColor = CanvasColor::Rgb(255, 0, 0);
+    const BLUE: CanvasColor = CanvasColor::Rgb(0, 0, 255);
+
+    fn bitmap(width: usize, height: usize, pixel: impl Fn(usize, usize) -> [u8; 4]) -> Bitmap {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| pixel(x, y))
+            .collect();
+        Bitmap {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn quantize_keeps_the_aspect_ratio_and_centers_the_image() {
+        // twice as wide as high, on a square canvas
+        let canvas = quantize(
+            &bitmap(4, 2, |_, _| [250, 10, 10, 255]),
+            (10, 10),
+            &[RED, BLUE],
+        );
+        for y in 0..10 {
+            for x in 0..10 {
+                let expected = if (2..7).contains(&y) { Some(RED) } else { None };
+                assert_eq!(canvas.get(Coord(x, y)), expected, "at {}, {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn quantize_leaves_transparent_pixels_empty() {
+        let image = bitmap(4, 4, |x, _| {
+            if x < 2 {
+                [0, 0, 0, 0]
+            } else {
+                [0, 0, 200, 255]
+            }
+        });
+        let canvas = quantize(&image, (8, 8), &[RED, BLUE]);
+        for y in 0..8 {
+            for x in 0..8 {
+                let expected = if x < 4 { None } else { Some(BLUE) };
+                assert_eq!(canvas.get(Coord(x, y)), expected, "at {}, {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn quantize_averages_the_pixels_of_a_cell() {
+        // half transparent cells are drawn with the color of their opaque pixels
+        let image = bitmap(4, 4, |x, y| match (x % 2, y % 2) {
+            (0, 0) => [0, 0, 0, 0],
+            _ => [255, 0, 0, 255],
+        });
+        let canvas = quantize(&image, (2, 2), &[RED, BLUE]);
+        for y in 0..2 {
+            for x in 0..2 {
+                assert_eq!(canvas.get(Coord(x, y)), Some(RED));
+            }
+        }
+    }
+
+    #[test]
+    fn png_palette_with_transparency() {
+        let bitmap = decode(include_bytes!("test_images/palette_trns.png")).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (5, 3));
+        let palette = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 128],
+            [255, 255, 255, 0],
+        ];
+        for y in 0..3 {
+            for x in 0..5 {
+                assert_eq!(bitmap.pixels[y * 5 + x], palette[(x + y) % 4]);
+            }
+        }
+    }
+
+    #[test]
+    fn png_sixteen_bit_rgb() {
+        let bitmap = decode(include_bytes!("test_images/rgb16.png")).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (4, 3));
+        for y in 0..3 {
+            for x in 0..4 {
+                let expected = [((x * 4097) >> 8) as u8, ((y * 4097) >> 8) as u8, 255, 255];
+                assert_eq!(bitmap.pixels[y * 4 + x], expected);
+            }
+        }
+    }
+
+    #[test]
+    fn png_interlaced_gray() {
+        let bitmap = decode(include_bytes!("test_images/gray_interlaced.png")).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (10, 9));
+        for y in 0..9 {
+            for x in 0..10 {
+                let gray = (x + y * 10) as u8;
+                assert_eq!(bitmap.pixels[y * 10 + x], [gray, gray, gray, 255]);
+            }
+        }
+    }
+
+    #[test]
+    fn png_rgb_with_transparent_color() {
+        let bitmap = decode(include_bytes!("test_images/rgb_trns.png")).unwrap();
+        for y in 0..6 {
+            for x in 0..6 {
+                let expected = if x == y {
+                    [0, 0, 0, 0]
+                } else {
+                    [x as u8 * 40, y as u8 * 40, 200, 255]
+                };
+                assert_eq!(bitmap.pixels[y * 6 + x], expected);
+            }
+        }
+    }
+
+    #[test]
+    fn png_rgba() {
+        let bitmap = decode(include_bytes!("test_images/rgba.png")).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (64, 48));
+        for y in 0..48 {
+            for x in 0..64 {
+                let expected = if (x / 16 + y / 16) % 2 == 1 {
+                    [255, 128, 0, 255]
+                } else {
+                    [0, 0, 255, x as u8 * 4]
+                };
+                assert_eq!(bitmap.pixels[y * 64 + x], expected);
+            }
+        }
+    }
+
+    /// jpegs lose some detail, so colors are only compared roughly
+    fn assert_close(actual: [u8; 4], expected: [u8; 4], tolerance: i32) {
+        let close = actual
+            .iter()
+            .zip(&expected)
+            .all(|(&a, &b)| (a as i32 - b as i32).abs() <= tolerance);
+        assert!(close, "{:?} is not close to {:?}", actual, expected);
+    }
+
+    #[test]
+    fn jpeg_gray() {
+        let bitmap = decode(include_bytes!("test_images/gray.jpg")).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (20, 12));
+        for y in 0..12 {
+            for x in 0..20 {
+                let gray = (x * 10 + y * 4) as u8;
+                assert_close(bitmap.pixels[y * 20 + x], [gray, gray, gray, 255], 2);
+            }
+        }
+    }
+
+    #[test]
+    fn jpeg_subsampled_color_with_restart_markers() {
+        let bitmap = decode(include_bytes!("test_images/color_420_restart.jpg")).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (32, 16));
+        for y in 0..16 {
+            for x in 0..32 {
+                let expected = [(x * 255 / 31) as u8, (y * 255 / 15) as u8, 128, 255];
+                assert_close(bitmap.pixels[y * 32 + x], expected, 24);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_pngs_are_rejected() {
+        let bytes = include_bytes!("test_images/rgba.png");
+        // without the checksums and the end chunk, which aren't needed to decode it
+        for len in 0..bytes.len() - 20 {
+            assert!(
+                decode(&bytes[..len]).is_err(),
+                "cut off after {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn oversized_images_are_rejected() {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 100_000, 100_000);
+        encoder.set_color(png::ColorType::Rgb);
+        let mut writer = encoder.write_header().unwrap();
+        // an empty data chunk, so only the header has to be read
+        writer.write_chunk(png::chunk::IDAT, &[]).unwrap();
+        drop(writer);
+        assert_eq!(
+            decode(&bytes).unwrap_err(),
+            "the image is too large (100000x100000)"
+        );
+    }
+
+    #[test]
+    fn unknown_formats_are_rejected() {
+        assert!(decode(b"GIF89a").is_err());
+        assert!(decode(b"").is_err());
+    }
+}
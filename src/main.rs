@@ -1,7 +1,12 @@
 pub mod client;
 pub mod data;
+pub mod export;
+pub mod gallery;
+pub mod import;
 pub mod message;
+pub mod replay;
 pub mod server;
+pub mod timelapse;
 
 use argh::FromArgs;
 use log::info;
@@ -19,6 +24,7 @@ use tui::{backend::CrosstermBackend, Terminal};
 
 use client::app::ServerSession;
 use data::Username;
+use message::Capability;
 pub use serde::{Deserialize, Serialize};
 
 #[derive(FromArgs)]
@@ -33,6 +39,8 @@ struct Opt {
 enum SubOpt {
     Server(server::CliOpts),
     Client(client::CliOpts),
+    Gallery(gallery::CliOpts),
+    Replay(replay::CliOpts),
 }
 
 fn display_public_ip(port: u32) {
@@ -59,7 +67,35 @@ async fn main() -> Result<()> {
             } else {
                 format!("ws://{}", addr)
             };
-            run_client(&addr, opt.username.into()).await.unwrap();
+            // binary messages are smaller, but json is easier to read when debugging
+            let mut capabilities = vec![Capability::Resume];
+            if !opt.json {
+                capabilities.push(Capability::BinaryEncoding);
+            }
+            let username: Username = opt.username.into();
+            let recorder = match opt.record {
+                Some(path) => match replay::Recorder::create(&path, &username) {
+                    Ok(recorder) => Some(recorder),
+                    Err(err) => {
+                        eprintln!("Could not record to {}: {}", path.display(), err);
+                        std::process::exit(1);
+                    }
+                },
+                None => None,
+            };
+            let result = run_client(
+                &addr,
+                username,
+                opt.room,
+                capabilities,
+                opt.half_blocks,
+                recorder,
+            )
+            .await;
+            if let Err(err) = result {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
         }
 
         SubOpt::Server(opt) => {
@@ -75,6 +111,20 @@ async fn main() -> Result<()> {
 
             server::server::run_server(opt).await.unwrap();
         }
+
+        SubOpt::Gallery(opt) => {
+            if let Err(err) = gallery::run(opt) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+
+        SubOpt::Replay(opt) => {
+            if let Err(err) = replay::run(opt).await {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
     }
     Ok(())
 }
@@ -83,13 +133,33 @@ pub enum ClientEvent {
     MouseInput(MouseEvent),
     KeyInput(KeyEvent),
     ServerMessage(message::ToClientMsg),
+    /// the connection to the server dropped
+    ConnectionLost,
+    /// we got back into our session after losing the connection
+    Reconnected(ServerSession, message::InitialState),
+    ReconnectFailed(String),
 }
 
-async fn run_client(addr: &str, username: Username) -> client::error::Result<()> {
-    let (mut client_evt_send, client_evt_recv) = tokio::sync::mpsc::channel::<ClientEvent>(1);
-
-    let mut app =
-        ServerSession::establish_connection(addr, username, client_evt_send.clone()).await?;
+async fn run_client(
+    addr: &str,
+    username: Username,
+    room: Option<String>,
+    capabilities: Vec<Capability>,
+    half_blocks: bool,
+    recorder: Option<replay::Recorder>,
+) -> client::error::Result<()> {
+    let (mut client_evt_send, client_evt_recv) = tokio::sync::mpsc::channel::<ClientEvent>(64);
+
+    let mut app = ServerSession::establish_connection(
+        addr,
+        username,
+        room,
+        capabilities,
+        client_evt_send.clone(),
+        recorder,
+    )
+    .await?;
+    app.half_blocks = half_blocks;
 
     enable_raw_mode()?;
     execute!(stdout(), EnterAlternateScreen)?;
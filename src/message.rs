@@ -1,27 +1,187 @@
-use crate::{data, server::skribbl::SkribblState};
-use serde::{Deserialize, Serialize};
+use crate::{
+    data::{self, Username},
+    server::{lobby::LobbyState, skribbl::SkribblState},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt::Display;
+
+/// version of the protocol spoken between client and server.
+/// bump this whenever a change to the messages would break older clients.
+pub const PROTOCOL_VERSION: u32 = 10;
+
+/// optional protocol features, agreed upon during the handshake
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// resuming a session after the connection dropped
+    Resume,
+    /// sending messages after the handshake as compact binary frames instead of json
+    BinaryEncoding,
+}
+
+/// how messages are put on the wire.
+/// the handshake is always json, afterwards it depends on the agreed capabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Binary,
+}
+
+impl Encoding {
+    pub fn from_capabilities(capabilities: &[Capability]) -> Self {
+        if capabilities.contains(&Capability::BinaryEncoding) {
+            Encoding::Binary
+        } else {
+            Encoding::Json
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, msg: &T) -> tungstenite::Message {
+        match self {
+            Encoding::Json => tungstenite::Message::Text(
+                serde_json::to_string(msg).expect("Could not serialize msg"),
+            ),
+            Encoding::Binary => tungstenite::Message::Binary(
+                bincode::serialize(msg).expect("Could not serialize msg"),
+            ),
+        }
+    }
+
+    /// decode a text or binary frame, whichever encoding was agreed on.
+    /// returns None for frames that don't carry a message, like pings.
+    pub fn decode<T: DeserializeOwned>(msg: &tungstenite::Message) -> Option<DecodeResult<T>> {
+        match msg {
+            tungstenite::Message::Text(text) => {
+                Some(serde_json::from_str(text).map_err(|err| err.to_string()))
+            }
+            tungstenite::Message::Binary(bytes) => {
+                Some(bincode::deserialize(bytes).map_err(|err| err.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// a decoded message, or a description of why it couldn't be decoded
+pub type DecodeResult<T> = std::result::Result<T, String>;
+
+/// the first message a client sends after connecting
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub client_name: String,
+    pub client_version: String,
+    pub username: Username,
+    /// the room to join, or the server's default room
+    pub room: Option<String>,
+    pub capabilities: Vec<Capability>,
+    /// token of an earlier session to resume, see InitialState
+    pub resume_token: Option<String>,
+}
+
+/// the server's answer to a Hello, sent right before the initial state
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Welcome {
+    pub protocol_version: u32,
+    pub server_version: String,
+    /// the room we ended up in
+    pub room: String,
+    /// the capabilities both the client and the server support
+    pub capabilities: Vec<Capability>,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ToClientMsg {
+    Welcome(Welcome),
     NewMessage(data::Message),
-    NewLine(data::Line),
+    /// everything drawn since the last batch was sent out, to be applied in this order
+    DrawOps(Vec<data::DrawOp>),
+    /// the stroke or fill with the given id was undone and should be removed from the canvas
+    Undo(u64),
+    /// a previously undone stroke or fill was put back
+    Redo(Vec<data::DrawOp>),
     InitialState(InitialState),
     SkribblStateChanged(SkribblState),
     GameOver(SkribblState),
     ClearCanvas,
     TimeChanged(u32),
+    /// the words the drawing user can choose from, only sent to that user
+    WordChoices(Vec<String>),
+    LobbyStateChanged(LobbyState),
+    HostChanged(Option<Username>),
+    /// sent instead of the initial state when the server doesn't let us join.
+    /// the server closes the connection right after.
+    JoinRejected(JoinRejectReason),
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum JoinRejectReason {
+    /// the username is empty, too long or contains characters that aren't allowed
+    InvalidUsername(String),
+    /// someone with that name is already in the room
+    UsernameTaken,
+    /// the host banned a player with that name from the room
+    Banned,
+    /// the session we tried to resume doesn't exist anymore
+    SessionExpired,
+    /// the client speaks a different protocol version than the server.
+    /// clients from before the handshake existed count as version 0.
+    IncompatibleVersion {
+        client_version: u32,
+        server_version: u32,
+    },
+}
+
+impl Display for JoinRejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinRejectReason::InvalidUsername(reason) => write!(f, "invalid username: {}", reason),
+            JoinRejectReason::UsernameTaken => write!(f, "that username is already taken"),
+            JoinRejectReason::Banned => write!(f, "you are banned from this room"),
+            JoinRejectReason::SessionExpired => write!(f, "your session expired"),
+            JoinRejectReason::IncompatibleVersion {
+                client_version,
+                server_version,
+            } => write!(
+                f,
+                "the server speaks protocol version {}, but the client speaks version {}",
+                server_version, client_version
+            ),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ToServerMsg {
+    /// introduce ourselves, has to be the first message on a new connection
+    Hello(Hello),
     NewMessage(data::Message),
     CommandMsg(data::CommandMsg),
-    NewLine(data::Line),
+    /// the next points of the stroke that's currently being drawn
+    Stroke(data::StrokeBatch),
+    /// fill an area of the canvas
+    Fill(data::Fill),
+    /// paste an image onto the canvas, only allowed in free draw rooms
+    Image(data::Image),
+    /// take back the most recent stroke, only allowed for the drawing user
+    Undo,
+    /// put back the most recently undone stroke, only allowed for the drawing user
+    Redo,
     ClearCanvas,
+    /// choose the word with the given index out of the offered word choices
+    ChooseWord(usize),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct InitialState {
-    pub lines: Vec<data::Line>,
-    pub dimensions: (usize, usize),
+    /// the canvas before `ops` were drawn on it
+    pub canvas: data::Canvas,
+    /// the most recent operations drawn on the canvas, which can still be undone
+    pub ops: Vec<data::DrawOp>,
     pub skribbl_state: Option<SkribblState>,
+    pub lobby_state: Option<LobbyState>,
+    pub host: Option<Username>,
+    /// the most recent chat messages
+    pub messages: Vec<data::Message>,
+    /// token to resume this session with when the connection drops
+    pub resume_token: String,
 }
@@ -0,0 +1,411 @@
+use crate::{
+    client::{
+        app::{App, ServerSession},
+        ui,
+    },
+    data::Username,
+    message::{InitialState, ToClientMsg, PROTOCOL_VERSION},
+    timelapse::{self, Frame},
+    ClientEvent,
+};
+use argh::FromArgs;
+use crossterm::{
+    event::{poll, read, Event, KeyCode},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{stdout, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tui::{backend::CrosstermBackend, Terminal};
+
+/// how often the screen is redrawn while playing a recording
+const FRAME_DURATION: Duration = Duration::from_millis(50);
+
+/// how far the arrow keys jump, in milliseconds of the recording
+const SEEK_STEP: u64 = 10_000;
+
+const MIN_SPEED: f64 = 0.125;
+const MAX_SPEED: f64 = 64.0;
+
+#[derive(FromArgs)]
+/// play back a game recorded with `termibbl client --record`.
+/// space pauses, the arrow keys seek and change the speed, q quits.
+#[argh(subcommand, name = "replay")]
+pub struct CliOpts {
+    #[argh(positional)]
+    /// the recording to play.
+    pub file: PathBuf,
+
+    #[argh(option, default = "1.0")]
+    /// how much faster than real time to play the recording, 1 by default.
+    pub speed: f64,
+
+    #[argh(switch)]
+    /// draw the canvas with half block characters, see `termibbl client --help`.
+    pub half_blocks: bool,
+
+    #[argh(option)]
+    /// instead of playing the recording, save how a turn was drawn to the given .gif or .cast file.
+    pub export: Option<PathBuf>,
+
+    #[argh(option)]
+    /// the turn to save with --export, counting from 1. only turns something was drawn in count.
+    /// the last one by default.
+    pub turn: Option<usize>,
+}
+
+/// the first line of a recording. every following line is a `[time, message]` pair,
+/// with the time in milliseconds since the recording started.
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordingHeader {
+    protocol_version: u32,
+    /// who the game was recorded as, the recording shows the game the way they saw it
+    username: Username,
+}
+
+/// writes every message the client gets from the server to a recording
+#[derive(Debug)]
+pub struct Recorder {
+    file: File,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, username: &Username) -> std::io::Result<Recorder> {
+        let mut recorder = Recorder {
+            file: File::create(path)?,
+            start: Instant::now(),
+        };
+        let header = RecordingHeader {
+            protocol_version: PROTOCOL_VERSION,
+            username: username.clone(),
+        };
+        recorder.write_line(&header)?;
+        Ok(recorder)
+    }
+
+    pub fn record(&mut self, msg: &ToClientMsg) -> std::io::Result<()> {
+        let time = self.start.elapsed().as_millis() as u64;
+        self.write_line(&(time, msg))
+    }
+
+    /// lines are written right away, so a recording survives the client crashing
+    fn write_line<T: Serialize>(&mut self, value: &T) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+}
+
+#[derive(Debug)]
+struct Recording {
+    username: Username,
+    initial_state: InitialState,
+    /// everything after the initial state, in the order it was received
+    messages: Vec<(u64, ToClientMsg)>,
+}
+
+impl Recording {
+    fn load(path: &Path) -> Result<Recording, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        Recording::parse(BufReader::new(file))
+    }
+
+    fn parse(reader: impl BufRead) -> Result<Recording, String> {
+        let mut lines = reader.lines().enumerate().peekable();
+
+        let header = match lines.next() {
+            Some((_, line)) => line.map_err(|err| err.to_string())?,
+            None => return Err("The recording is empty".to_string()),
+        };
+        let header: RecordingHeader =
+            serde_json::from_str(&header).map_err(|_| "This is not a termibbl recording")?;
+        if header.protocol_version != PROTOCOL_VERSION {
+            return Err(format!(
+                "The recording was made with protocol version {}, this version of termibbl uses {}",
+                header.protocol_version, PROTOCOL_VERSION
+            ));
+        }
+
+        let mut messages = Vec::new();
+        while let Some((idx, line)) = lines.next() {
+            let line = line.map_err(|err| err.to_string())?;
+            match serde_json::from_str(&line) {
+                Ok(entry) => messages.push(entry),
+                // the last line is cut off if the client didn't exit cleanly
+                Err(_) if lines.peek().is_none() => break,
+                Err(err) => {
+                    return Err(format!(
+                        "Line {} of the recording is invalid: {}",
+                        idx + 1,
+                        err
+                    ))
+                }
+            }
+        }
+        if messages.is_empty() {
+            return Err("The recording ends before the game started".to_string());
+        }
+        let initial_state = match messages.remove(0) {
+            (_, ToClientMsg::InitialState(state)) => state,
+            _ => return Err("The recording doesn't start with the state of the game".to_string()),
+        };
+        Ok(Recording {
+            username: header.username,
+            initial_state,
+            messages,
+        })
+    }
+
+    fn duration(&self) -> u64 {
+        self.messages.last().map_or(0, |(time, _)| *time)
+    }
+}
+
+/// the state of the game at some point of a recording
+struct Player<'a> {
+    recording: &'a Recording,
+    app: App,
+    half_blocks: bool,
+    /// the index of the next message to apply
+    next: usize,
+    time: u64,
+}
+
+impl<'a> Player<'a> {
+    fn new(recording: &'a Recording, half_blocks: bool) -> Player<'a> {
+        let session = ServerSession::offline(recording.username.clone());
+        let mut app = App::new(session, recording.initial_state.clone());
+        app.half_blocks = half_blocks;
+        Player {
+            recording,
+            app,
+            half_blocks,
+            next: 0,
+            time: 0,
+        }
+    }
+
+    /// apply the next message
+    async fn step(&mut self) -> crate::client::error::Result<()> {
+        if let Some((time, msg)) = self.recording.messages.get(self.next) {
+            match msg {
+                // the client reconnected
+                ToClientMsg::InitialState(state) => self.app.apply_initial_state(state.clone()),
+                msg => {
+                    self.app
+                        .handle_event(ClientEvent::ServerMessage(msg.clone()))
+                        .await?
+                }
+            }
+            self.next += 1;
+            self.time = *time;
+        }
+        Ok(())
+    }
+
+    /// apply all messages received up to the given time
+    async fn advance_to(&mut self, time: u64) -> crate::client::error::Result<()> {
+        while let Some((msg_time, _)) = self.recording.messages.get(self.next) {
+            if *msg_time > time {
+                break;
+            }
+            self.step().await?;
+        }
+        self.time = time;
+        Ok(())
+    }
+
+    fn frame(&self) -> Frame {
+        Frame {
+            time: self.time,
+            canvas: self.app.canvas.history.pixels().clone(),
+        }
+    }
+
+    fn is_choosing_word(&self) -> bool {
+        self.app
+            .game_state
+            .as_ref()
+            .is_some_and(|state| state.is_choosing_word())
+    }
+
+    /// jump to the given time. messages can't be taken back, so going back starts over from the beginning.
+    async fn seek(&mut self, time: u64) -> crate::client::error::Result<()> {
+        if time < self.time {
+            *self = Player::new(self.recording, self.half_blocks);
+        }
+        self.advance_to(time).await
+    }
+}
+
+/// play the recording until the user quits
+pub async fn run(opt: CliOpts) -> Result<(), String> {
+    let recording = Recording::load(&opt.file)
+        .map_err(|err| format!("Could not load {}: {}", opt.file.display(), err))?;
+
+    if let Some(path) = opt.export {
+        let turns = turns(&recording).await.map_err(|err| err.to_string())?;
+        let frames = match opt.turn {
+            Some(turn) => turn.checked_sub(1).and_then(|idx| turns.get(idx)),
+            None => turns.last(),
+        }
+        .ok_or_else(|| format!("The recording has {} turns with drawings", turns.len()))?;
+        return timelapse::save(frames, &path, opt.speed, opt.half_blocks)
+            .map_err(|err| format!("Could not save {}: {}", path.display(), err));
+    }
+
+    let result = play(&recording, opt.speed, opt.half_blocks).await;
+    let _ = execute!(stdout(), LeaveAlternateScreen);
+    let _ = disable_raw_mode();
+    result.map_err(|err| err.to_string())
+}
+
+async fn play(
+    recording: &Recording,
+    speed: f64,
+    half_blocks: bool,
+) -> crate::client::error::Result<()> {
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+
+    let mut player = Player::new(recording, half_blocks);
+    let mut speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    let mut paused = false;
+    let mut last_frame = Instant::now();
+    loop {
+        let elapsed = last_frame.elapsed();
+        last_frame = Instant::now();
+        if !paused {
+            let time = player.time + (elapsed.as_millis() as f64 * speed) as u64;
+            player.advance_to(time.min(recording.duration())).await?;
+        }
+        if player.time >= recording.duration() {
+            paused = true;
+        }
+
+        // nobody types during a replay, so the chat input shows where we are instead
+        player.app.chat.input = format!(
+            "{} {} / {} at {}x",
+            if paused { "paused" } else { "playing" },
+            format_duration(player.time),
+            format_duration(recording.duration()),
+            speed
+        );
+        ui::draw(&mut player.app, &mut terminal)?;
+
+        if !poll(FRAME_DURATION)? {
+            continue;
+        }
+        if let Event::Key(key) = read()? {
+            match key.code {
+                KeyCode::Char(' ') => {
+                    if player.time >= recording.duration() {
+                        player.seek(0).await?;
+                    }
+                    paused = !paused;
+                }
+                KeyCode::Left => player.seek(player.time.saturating_sub(SEEK_STEP)).await?,
+                KeyCode::Right => {
+                    let time = (player.time + SEEK_STEP).min(recording.duration());
+                    player.seek(time).await?;
+                }
+                KeyCode::Home => player.seek(0).await?,
+                KeyCode::Char('+') | KeyCode::Up => speed = (speed * 2.0).min(MAX_SPEED),
+                KeyCode::Char('-') | KeyCode::Down => speed = (speed / 2.0).max(MIN_SPEED),
+                KeyCode::Esc | KeyCode::Char('q') => break Ok(()),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// every state the canvas was in, split into the turns of the recorded games.
+/// a turn starts when a player begins choosing a word, turns nothing was drawn in are left out.
+/// without a game the whole recording counts as one turn.
+async fn turns(recording: &Recording) -> crate::client::error::Result<Vec<Vec<Frame>>> {
+    let mut player = Player::new(recording, false);
+    let mut turns = vec![vec![player.frame()]];
+    while let Some((_, msg)) = recording.messages.get(player.next) {
+        let was_choosing_word = player.is_choosing_word();
+        player.step().await?;
+        if player.is_choosing_word() && !was_choosing_word {
+            turns.push(vec![player.frame()]);
+            continue;
+        }
+        match msg {
+            ToClientMsg::DrawOps(_)
+            | ToClientMsg::Undo(_)
+            | ToClientMsg::Redo(_)
+            | ToClientMsg::ClearCanvas
+            | ToClientMsg::InitialState(_) => {
+                if let Some(turn) = turns.last_mut() {
+                    turn.push(player.frame());
+                }
+            }
+            _ => {}
+        }
+    }
+    // turns end with the canvas being cleared, but should end with the finished drawing
+    for frames in &mut turns {
+        while frames.len() > 1 && frames.last().is_some_and(|frame| frame.canvas.is_empty()) {
+            frames.pop();
+        }
+    }
+    turns.retain(|frames| frames.iter().any(|frame| !frame.canvas.is_empty()));
+    Ok(turns)
+}
+
+/// format milliseconds as minutes and seconds, like `3:07`
+fn format_duration(millis: u64) -> String {
+    let seconds = millis / 1000;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = r#"{"protocol_version":PROTOCOL,"username":"someone"}"#;
+    const INITIAL_STATE: &str = r#"[0,{"InitialState":{"canvas":{"width":2,"height":1,"cells":[[2,null]]},"ops":[],"skribbl_state":null,"lobby_state":null,"host":null,"messages":[],"resume_token":"token"}}]"#;
+
+    fn parse(lines: &[&str]) -> Result<Recording, String> {
+        let header = HEADER.replace("PROTOCOL", &PROTOCOL_VERSION.to_string());
+        let recording = std::iter::once(header.as_str())
+            .chain(lines.iter().copied())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Recording::parse(recording.as_bytes())
+    }
+
+    #[test]
+    fn a_cut_off_last_line_is_skipped() {
+        let recording = parse(&[INITIAL_STATE, r#"[10,"ClearCanvas"]"#, r#"[20,"Clea"#]).unwrap();
+        assert_eq!(recording.messages.len(), 1);
+        assert_eq!(recording.duration(), 10);
+    }
+
+    #[test]
+    fn invalid_lines_before_the_end_are_errors() {
+        let err = parse(&[INITIAL_STATE, "garbage", r#"[20,"ClearCanvas"]"#])
+            .map(|_| ())
+            .unwrap_err();
+        assert!(
+            err.starts_with("Line 3 of the recording is invalid"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn recordings_have_to_start_with_the_game_state() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&[r#"[10,"ClearCanvas"]"#]).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use crate::data::Username;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// state of a room while waiting for players before a game
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LobbyState {
+    /// all players in the lobby, and whether they are ready to play
+    pub players: BTreeMap<Username, bool>,
+
+    /// the number of players needed to start a game
+    pub min_players: usize,
+}
+
+impl LobbyState {
+    pub fn new(players: Vec<Username>, min_players: usize) -> Self {
+        LobbyState {
+            players: players.into_iter().map(|player| (player, false)).collect(),
+            min_players,
+        }
+    }
+
+    pub fn add_player(&mut self, username: Username) {
+        self.players.entry(username).or_insert(false);
+    }
+
+    pub fn remove_player(&mut self, username: &Username) {
+        self.players.remove(username);
+    }
+
+    /// toggles whether the player is ready, returning the new value
+    pub fn toggle_ready(&mut self, username: &Username) -> bool {
+        match self.players.get_mut(username) {
+            Some(ready) => {
+                *ready = !*ready;
+                *ready
+            }
+            None => false,
+        }
+    }
+
+    /// check whether the host can start the game.
+    /// the host is always considered ready, everyone else has to be ready.
+    pub fn check_can_start(&self, host: &Username) -> Result<(), String> {
+        if self.players.len() < self.min_players {
+            return Err(format!(
+                "Need at least {} players to start, but there are only {}",
+                self.min_players,
+                self.players.len()
+            ));
+        }
+        let not_ready = self
+            .players
+            .iter()
+            .filter(|(username, ready)| !**ready && *username != host)
+            .map(|(username, _)| username.to_string())
+            .collect::<Vec<_>>();
+        if !not_ready.is_empty() {
+            return Err(format!(
+                "Not everyone is ready yet: {}",
+                not_ready.join(", ")
+            ));
+        }
+        Ok(())
+    }
+}
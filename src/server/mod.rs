@@ -1,14 +1,19 @@
-use std::io::Read;
+use std::{io::Read, path::PathBuf};
 
 use argh::FromArgs;
 use log::{debug, info};
+use skribbl::GameSettings;
 
+use crate::data::{Username, MAX_CANVAS_CELLS};
+
+pub mod lobby;
 pub mod server;
 pub mod skribbl;
 
-const DIMEN: (usize, usize) = (900, 60);
+const DIMEN: (usize, usize) = (100, 80);
 const ROUND_DURATION: usize = 120;
 const ROUNDS: usize = 3;
+const MIN_PLAYERS: usize = 2;
 
 #[derive(FromArgs)]
 /// host a Termibbl session
@@ -30,13 +35,31 @@ pub struct CliOpts {
     /// default number of rounds per game
     rounds: usize,
 
-    /// default canvas dimensions <width>x<height>
+    #[argh(option, default = "MIN_PLAYERS")]
+    /// minimum number of players needed to start a game
+    min_players: usize,
+
+    #[argh(option)]
+    /// username that is made the host of a room whenever they join it.
+    /// otherwise, the first player to join a room becomes its host
+    host: Option<String>,
+
+    /// default canvas dimensions <width>x<height>, where two rows fit into one line of the terminal
     #[argh(option, from_str_fn(parse_dimension), default = "DIMEN")]
     dimensions: (usize, usize),
 
     /// optional path to custom word list
     #[argh(option, from_str_fn(read_words_file))]
     words: Option<Vec<String>>,
+
+    #[argh(option)]
+    /// directory to save every drawing to as png and svg, when its turn ends or the canvas is cleared
+    export_dir: Option<PathBuf>,
+
+    #[argh(option)]
+    /// directory to save the drawing of every turn to, together with its word, drawer and guessers.
+    /// browse them with `termibbl gallery <dir>`
+    gallery_dir: Option<PathBuf>,
 }
 
 fn parse_dimension(s: &str) -> Result<(usize, usize), String> {
@@ -45,10 +68,17 @@ fn parse_dimension(s: &str) -> Result<(usize, usize), String> {
         .map(str::parse)
         .filter_map(std::result::Result::ok);
 
-    split
+    let (width, height): (usize, usize) = split
         .next()
         .and_then(|width| split.next().map(|height| (width, height)))
-        .ok_or_else(|| "could not parse dimensions".to_owned())
+        .ok_or_else(|| "could not parse dimensions".to_owned())?;
+    match width.checked_mul(height) {
+        Some(cells) if cells <= MAX_CANVAS_CELLS => Ok((width, height)),
+        _ => Err(format!(
+            "the canvas can't have more than {} cells",
+            MAX_CANVAS_CELLS
+        )),
+    }
 }
 
 fn read_words_file(path: &str) -> Result<Vec<String>, String> {
@@ -68,26 +98,31 @@ fn read_words_file(path: &str) -> Result<Vec<String>, String> {
         .collect::<Vec<String>>())
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct GameOpts {
     pub dimensions: (usize, usize),
     pub words: Vec<String>,
-    pub number_of_rounds: usize,
-    pub round_duration: usize,
+    pub settings: GameSettings,
+    pub min_players: usize,
+    pub host: Option<Username>,
+    pub export_dir: Option<PathBuf>,
+    pub gallery_dir: Option<PathBuf>,
 }
 
 impl From<CliOpts> for GameOpts {
     fn from(opt: CliOpts) -> Self {
         let default_words = opt.words.unwrap_or_else(Vec::new);
         let default_dimensions = opt.dimensions;
-        let default_round_duration = opt.round_duration;
-        let default_number_of_rounds = opt.rounds;
+        let default_settings = GameSettings::new(opt.round_duration as u64, opt.rounds);
 
         Self {
             dimensions: default_dimensions,
             words: default_words,
-            number_of_rounds: default_number_of_rounds,
-            round_duration: default_round_duration,
+            settings: default_settings,
+            min_players: opt.min_players,
+            host: opt.host.map(Username::from),
+            export_dir: opt.export_dir,
+            gallery_dir: opt.gallery_dir,
         }
     }
 }
@@ -1,21 +1,65 @@
 //https://github.com/snapview/tokio-tungstenite/blob/master/examples/server.rs
 
-use super::{skribbl::SkribblState, CliOpts};
+use super::{
+    lobby::LobbyState,
+    skribbl::{get_time_now, PlayerState, SkribblState},
+    CliOpts, GameOpts,
+};
 use crate::{
-    data,
-    message::{InitialState, ToClientMsg, ToServerMsg},
+    data, export,
+    gallery::Snapshot,
+    message::{
+        Capability, Encoding, Hello, InitialState, JoinRejectReason, ToClientMsg, ToServerMsg,
+        Welcome, PROTOCOL_VERSION,
+    },
 };
-use data::{CommandMsg, Message, Username};
+use data::{Canvas, CommandMsg, DrawOp, History, Message, Username};
 use futures_timer::Delay;
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use log::{info, warn};
+use rand::{distributions::Alphanumeric, Rng};
 use std::net::SocketAddr;
-use std::{cmp::min, collections::HashMap, time::Duration};
+use std::{
+    cmp::min,
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio::{
     net::{TcpListener, TcpStream},
-    sync::Mutex,
+    sync::{oneshot, Mutex},
 };
+use tokio_tungstenite::WebSocketStream;
+use tungstenite::protocol::{frame::coding::CloseCode, CloseFrame, WebSocketConfig};
+
+/// the room clients end up in if they don't request a specific one
+pub const DEFAULT_ROOM: &str = "main";
+
+/// the optional protocol features this server supports
+const SERVER_CAPABILITIES: &[Capability] = &[Capability::Resume, Capability::BinaryEncoding];
+
+/// seconds a disconnected player has to reconnect before their score is gone
+const RECONNECT_GRACE_PERIOD: u64 = 60;
 
-pub const ROUND_DURATION: u64 = 120;
+/// how often drawing operations are sent out to everyone. operations in the meantime are sent together.
+const DRAW_FLUSH_INTERVAL: Duration = Duration::from_millis(30);
+
+/// how many messages can be queued for a connection, or events for a room, before sending has to wait
+const SESSION_QUEUE_LENGTH: usize = 64;
+const ROOM_QUEUE_LENGTH: usize = 64;
+
+/// the largest message a client may send, in bytes. the largest ones are images,
+/// which are limited to the size of the canvas anyway, so this only stops clients from sending huge nonsense.
+const MAX_MESSAGE_SIZE: usize = 4 << 20;
+
+/// number of chat messages that are kept to show to (re)joining players
+const CHAT_HISTORY_LENGTH: usize = 100;
 
 type Result<T> = std::result::Result<T, ServerError>;
 
@@ -41,17 +85,38 @@ impl From<std::io::Error> for ServerError {
     fn from(err: std::io::Error) -> Self { ServerError::IOError(err) }
 }
 
+/// whether a user was allowed to join a room
+type JoinResult = std::result::Result<(), JoinRejectReason>;
+
+/// a user that wants to join a room
+#[derive(Debug)]
+struct JoinRequest {
+    session: UserSession,
+    /// token of an earlier session of this user, which should be resumed
+    resume_token: Option<String>,
+    /// where to send the result once the server decided whether to let the user in
+    result_send: oneshot::Sender<JoinResult>,
+}
+
 #[derive(Debug)]
 enum ServerEvent {
     ToServerMsg(Username, ToServerMsg),
-    UserJoined(UserSession),
-    UserLeft(Username),
+    UserJoined(JoinRequest),
+    UserLeft(Username, SessionId),
     Tick,
 }
 
+/// identifies a single connection, as the same user might be connected more than once while reconnecting
+type SessionId = u64;
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Debug)]
 struct UserSession {
+    id: SessionId,
     username: Username,
+    /// secret the user can use to resume this session after losing the connection
+    resume_token: String,
     msg_send: Mutex<tokio::sync::mpsc::Sender<ToClientMsg>>,
     close_send: tokio::sync::mpsc::Sender<()>,
 }
@@ -63,7 +128,9 @@ impl UserSession {
         close_send: tokio::sync::mpsc::Sender<()>,
     ) -> Self {
         UserSession {
+            id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
             username,
+            resume_token: generate_resume_token(),
             msg_send: Mutex::new(msg_send),
             close_send,
         }
@@ -80,60 +147,377 @@ impl UserSession {
     }
 }
 
+/// a player whose connection dropped, who can still resume their session.
+/// they stay in the game until they either come back or their time runs out.
+#[derive(Debug)]
+struct DisconnectedPlayer {
+    resume_token: String,
+    /// time after which the player can't resume anymore and is removed from the room
+    expires_at: u64,
+}
+
+fn generate_resume_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .collect()
+}
+
 #[derive(Debug)]
 pub enum GameState {
     FreeDraw,
-    Skribbl(SkribblState),
+    Lobby(LobbyState),
+    Skribbl(Box<SkribblState>),
 }
 
 impl GameState {
+    /// the state of a room while no game is running.
+    /// that's a lobby waiting for the next game if there are words to play with, free draw otherwise.
+    fn idle(game_opts: &GameOpts, players: Vec<Username>) -> Self {
+        if game_opts.words.is_empty() {
+            GameState::FreeDraw
+        } else {
+            GameState::Lobby(LobbyState::new(players, game_opts.min_players))
+        }
+    }
+
     fn skribbl_state(&self) -> Option<&SkribblState> {
         match self {
             GameState::Skribbl(state) => Some(state),
             _ => None,
         }
     }
+
+    fn lobby_state(&self) -> Option<&LobbyState> {
+        match self {
+            GameState::Lobby(state) => Some(state),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
 struct ServerState {
+    /// the name of the room this is the state of
+    room: String,
     sessions: HashMap<Username, UserSession>,
-    pub lines: Vec<data::Line>,
-    pub dimensions: (usize, usize),
+    /// the canvas, and the operations drawn on it that can still be undone
+    pub canvas: History,
+    /// operations that were undone and can be redone, the most recent one last.
+    /// a stroke can consist of several batches if it was drawn at the same time as another one.
+    undone_ops: Vec<Vec<DrawOp>>,
+    /// operations that were drawn, but not yet sent out to everyone
+    pending_ops: Vec<DrawOp>,
     pub game_state: GameState,
-    pub words: Option<Vec<String>>,
+    pub game_opts: GameOpts,
+    /// the player who can start games and moderate the room.
+    /// that's the configured host if they're here, the first player to join otherwise.
+    pub host: Option<Username>,
+    /// players whose chat messages aren't shown to anyone
+    pub muted: HashSet<Username>,
+    /// players that can't join this room again
+    pub banned: HashSet<Username>,
+    /// players that lost their connection, but can still come back
+    disconnected: HashMap<Username, DisconnectedPlayer>,
+    /// the most recent chat messages
+    chat_history: Mutex<VecDeque<Message>>,
+    /// how many drawings were exported or saved to the gallery, to give each file a unique name
+    saved_drawings: u64,
 }
 
 impl ServerState {
-    fn new(game_state: GameState, dimensions: (usize, usize), words: Option<Vec<String>>) -> Self {
+    fn new(room: String, game_opts: GameOpts) -> Self {
         ServerState {
+            room,
             sessions: HashMap::new(),
-            lines: Vec::new(),
-            dimensions,
-            game_state,
-            words,
+            canvas: History::new(Canvas::new(game_opts.dimensions), Vec::new()),
+            undone_ops: Vec::new(),
+            pending_ops: Vec::new(),
+            game_state: GameState::idle(&game_opts, Vec::new()),
+            game_opts,
+            host: None,
+            muted: HashSet::new(),
+            banned: HashSet::new(),
+            disconnected: HashMap::new(),
+            chat_history: Mutex::new(VecDeque::new()),
+            saved_drawings: 0,
+        }
+    }
+
+    /// make the given player the host, letting everyone know
+    async fn set_host(&mut self, host: Option<Username>) -> Result<()> {
+        self.host = host;
+        self.broadcast(ToClientMsg::HostChanged(self.host.clone()))
+            .await?;
+        if let Some(host) = &self.host {
+            self.broadcast_system_msg(format!("{} is now the host", host))
+                .await?;
         }
+        Ok(())
     }
 
+    /// remove a player from the room and from the running game
     async fn remove_player(&mut self, username: &Username) -> Result<()> {
-        self.sessions.remove(username).map(|x| x.close());
+        if let Some(session) = self.sessions.remove(username) {
+            // the connection might already be gone, in which case there's nothing left to close
+            let _ = session.close().await;
+        }
+
+        if self.host.as_ref() == Some(username) {
+            let new_host = self.sessions.keys().min().cloned();
+            self.set_host(new_host).await?;
+        }
+
+        match &mut self.game_state {
+            GameState::Skribbl(state) => {
+                state.remove_user(username);
+                if state.is_drawing(username) {
+                    self.end_turn().await?;
+                } else {
+                    let state = SkribblState::clone(state);
+                    self.broadcast(ToClientMsg::SkribblStateChanged(state))
+                        .await?;
+                }
+            }
+            GameState::Lobby(state) => {
+                state.remove_player(username);
+                let state = state.clone();
+                self.broadcast(ToClientMsg::LobbyStateChanged(state))
+                    .await?;
+            }
+            GameState::FreeDraw => {}
+        }
+        Ok(())
+    }
+
+    /// a connection was closed. the player stays in the game for a while, so they can reconnect,
+    /// and is only removed from it once that time is over.
+    async fn on_user_left(&mut self, username: &Username, session_id: SessionId) -> Result<()> {
+        let resume_token = match self.sessions.get(username) {
+            // an older connection of a player who already reconnected
+            Some(session) if session.id != session_id => return Ok(()),
+            Some(session) => session.resume_token.clone(),
+            None => return Ok(()),
+        };
+        if let Some(session) = self.sessions.remove(username) {
+            let _ = session.close().await;
+        }
+        self.disconnected.insert(
+            username.clone(),
+            DisconnectedPlayer {
+                resume_token,
+                expires_at: get_time_now() + RECONNECT_GRACE_PERIOD,
+            },
+        );
+        self.broadcast_system_msg(format!("{} lost their connection", username))
+            .await?;
+        Ok(())
+    }
+
+    /// everyone in the room. players that lost their connection are still part of it until their time runs out.
+    fn players(&self) -> Vec<Username> {
+        self.sessions
+            .keys()
+            .chain(self.disconnected.keys())
+            .cloned()
+            .collect()
+    }
+
+    fn is_in_room(&self, username: &Username) -> bool {
+        self.sessions.contains_key(username) || self.disconnected.contains_key(username)
+    }
+
+    /// start a new game with the given players
+    async fn start_game(&mut self, players: Vec<Username>) -> Result<()> {
+        let skribbl_state = SkribblState::new(
+            players,
+            self.game_opts.words.clone(),
+            self.game_opts.settings.clone(),
+        );
+        self.game_state = GameState::Skribbl(Box::new(skribbl_state.clone()));
+        self.clear_canvas();
+        tokio::try_join!(
+            self.broadcast(ToClientMsg::ClearCanvas),
+            self.broadcast(ToClientMsg::SkribblStateChanged(skribbl_state)),
+            self.broadcast_system_msg("The game has started!".to_string()),
+        )?;
+        self.send_word_choices().await?;
+        Ok(())
+    }
+
+    /// end the current turn, revealing the word and clearing the canvas.
+    /// then either start the next turn or, if this was the last turn of the last round, end the game.
+    async fn end_turn(&mut self) -> Result<()> {
         let state = match &mut self.game_state {
             GameState::Skribbl(state) => state,
             _ => return Ok(()),
         };
-        if state.is_drawing(username) {
-            state.next_turn();
+
+        let old_word = state.current_word().to_string();
+        let snapshot = Snapshot {
+            room: self.room.clone(),
+            word: old_word.clone(),
+            drawer: state.drawing_user.clone(),
+            solved_by: state
+                .player_states
+                .iter()
+                .filter(|(_, player)| player.has_solved)
+                .map(|(username, _)| username.clone())
+                .collect(),
+            time: get_time_now(),
+            canvas: self.canvas.pixels().clone(),
+        };
+        let has_next_turn = state.next_turn();
+        let state = SkribblState::clone(state);
+
+        self.export_drawing(Some(&old_word));
+        self.save_to_gallery(snapshot);
+        self.clear_canvas();
+        tokio::try_join!(
+            self.broadcast(ToClientMsg::ClearCanvas),
+            self.broadcast_system_msg(format!("The word was: \"{}\"", old_word)),
+        )?;
+
+        if has_next_turn {
+            self.broadcast(ToClientMsg::SkribblStateChanged(state))
+                .await?;
+            self.send_word_choices().await?;
+        } else {
+            self.end_game(state).await?;
+        }
+        Ok(())
+    }
+
+    /// end the running game, sending the final standings to everyone and going back to the lobby.
+    async fn end_game(&mut self, final_state: SkribblState) -> Result<()> {
+        self.game_state = GameState::idle(&self.game_opts, self.players());
+        self.broadcast(ToClientMsg::GameOver(final_state)).await?;
+        if let Some(state) = self.game_state.lobby_state() {
+            self.broadcast(ToClientMsg::LobbyStateChanged(state.clone()))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// send the words to choose from to the drawing user, if they are currently choosing a word
+    async fn send_word_choices(&self) -> Result<()> {
+        let state = match self.game_state.skribbl_state() {
+            Some(state) if state.is_choosing_word() => state,
+            _ => return Ok(()),
+        };
+        if let Some(session) = self.sessions.get(&state.drawing_user) {
+            let choices = state.word_choices().to_vec();
+            session.send(ToClientMsg::WordChoices(choices)).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_choose_word(&mut self, username: &Username, idx: usize) -> Result<()> {
+        if let GameState::Skribbl(ref mut state) = self.game_state {
+            if state.is_drawing(username) && state.choose_word(idx) {
+                let state = SkribblState::clone(state);
+                self.broadcast(ToClientMsg::SkribblStateChanged(state))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn on_toggle_ready(&mut self, username: &Username) -> Result<()> {
+        if let GameState::Lobby(ref mut state) = self.game_state {
+            let is_ready = state.toggle_ready(username);
+            let state = state.clone();
+            tokio::try_join!(
+                self.broadcast(ToClientMsg::LobbyStateChanged(state)),
+                self.broadcast_system_msg(format!(
+                    "{} is {}",
+                    username,
+                    if is_ready { "ready" } else { "not ready" }
+                )),
+            )?;
+        }
+        Ok(())
+    }
+
+    async fn on_start_game(&mut self, username: &Username) -> Result<()> {
+        let state = match &self.game_state {
+            GameState::Lobby(state) => state,
+            _ => return Ok(()),
+        };
+        if let Err(reason) = state.check_can_start(username) {
+            return self.send_system_msg(username, reason).await;
         }
-        state.remove_user(username);
-        let state = state.clone();
-        self.broadcast(ToClientMsg::SkribblStateChanged(state))
+        let players = state.players.keys().cloned().collect();
+        self.start_game(players).await
+    }
+
+    async fn on_kick_player(&mut self, kicked_player: &Username, reason: &str) -> Result<()> {
+        let _ = self
+            .send_system_msg(kicked_player, format!("You were {} by the host", reason))
+            .await;
+        self.remove_player(kicked_player).await?;
+        self.disconnected.remove(kicked_player);
+        self.broadcast_system_msg(format!("{} was {}", kicked_player, reason))
             .await?;
         Ok(())
     }
 
-    async fn on_command_msg(&mut self, _username: &Username, msg: &CommandMsg) -> Result<()> {
+    async fn on_skip_turn(&mut self) -> Result<()> {
+        if let GameState::Skribbl(_) = self.game_state {
+            self.broadcast_system_msg("The host skipped this turn".to_string())
+                .await?;
+            self.end_turn().await?;
+        }
+        Ok(())
+    }
+
+    async fn on_restart_game(&mut self) -> Result<()> {
+        if self.game_opts.words.is_empty() {
+            return Ok(());
+        }
+        self.start_game(self.players()).await
+    }
+
+    async fn on_command_msg(&mut self, username: &Username, msg: &CommandMsg) -> Result<()> {
+        if msg.requires_host() && self.host.as_ref() != Some(username) {
+            return self
+                .send_system_msg(username, "Only the host can do that".to_string())
+                .await;
+        }
         match msg {
-            CommandMsg::KickPlayer(kicked_player) => self.remove_player(kicked_player).await?,
+            CommandMsg::KickPlayer(player)
+            | CommandMsg::BanPlayer(player)
+            | CommandMsg::MutePlayer(player)
+                if !self.is_in_room(player) =>
+            {
+                self.send_system_msg(username, format!("There is no player called {}", player))
+                    .await?
+            }
+            CommandMsg::UnmutePlayer(player) if !self.muted.contains(player) => {
+                self.send_system_msg(username, format!("{} isn't muted", player))
+                    .await?
+            }
+            CommandMsg::KickPlayer(kicked_player) => {
+                self.on_kick_player(kicked_player, "kicked").await?
+            }
+            CommandMsg::BanPlayer(banned_player) => {
+                self.banned.insert(banned_player.clone());
+                self.on_kick_player(banned_player, "banned").await?
+            }
+            CommandMsg::MutePlayer(muted_player) => {
+                self.muted.insert(muted_player.clone());
+                self.broadcast_system_msg(format!("{} was muted", muted_player))
+                    .await?;
+            }
+            CommandMsg::UnmutePlayer(muted_player) => {
+                self.muted.remove(muted_player);
+                self.broadcast_system_msg(format!("{} was unmuted", muted_player))
+                    .await?;
+            }
+            CommandMsg::SkipTurn => self.on_skip_turn().await?,
+            CommandMsg::RestartGame => self.on_restart_game().await?,
+            CommandMsg::ToggleReady => self.on_toggle_ready(username).await?,
+            CommandMsg::StartGame => self.on_start_game(username).await?,
         }
         Ok(())
     }
@@ -144,7 +528,9 @@ impl ServerState {
             GameState::Skribbl(ref mut state) => {
                 let can_guess = state.can_guess(&username);
                 let remaining_time = state.remaining_time();
+                let score_increase = state.settings.calculate_score_increase(remaining_time);
                 let current_word = state.current_word().to_string();
+                let is_choosing_word = state.is_choosing_word();
                 let noone_already_solved = state
                     .player_states
                     .iter()
@@ -156,54 +542,34 @@ impl ServerState {
                         if noone_already_solved {
                             state.round_end_time -= remaining_time as u64 / 2;
                         }
-                        player_state.on_solve(remaining_time);
+                        player_state.on_solve(score_increase);
                         let all_solved = state.did_all_solve();
-                        if all_solved {
-                            state.next_turn();
-                        }
-                        let state = state.clone();
+                        let state = SkribblState::clone(state);
                         tokio::try_join!(
                             self.broadcast(ToClientMsg::SkribblStateChanged(state)),
                             self.broadcast_system_msg(format!("{} guessed it!", username)),
                         )?;
                         if all_solved {
-                            self.lines.clear();
-                            tokio::try_join!(
-                                self.broadcast(ToClientMsg::ClearCanvas),
-                                self.broadcast_system_msg(format!(
-                                    "The word was: \"{}\"",
-                                    current_word
-                                ))
-                            )?;
+                            self.end_turn().await?;
                         }
-                    } else if is_very_close_to(msg.text().to_string(), current_word.to_string()) {
+                    } else if !is_choosing_word
+                        && is_very_close_to(msg.text().to_string(), current_word.to_string())
+                    {
                         should_broadcast = false;
                         if can_guess {
-                            self.send_to(
-                                &username,
-                                ToClientMsg::NewMessage(Message::SystemMsg(
-                                    "You're very close!".to_string(),
-                                )),
-                            )
-                            .await?;
+                            self.send_system_msg(&username, "You're very close!".to_string())
+                                .await?;
                         }
                     }
                 }
             }
-            GameState::FreeDraw => {
-                if let Some(words) = &self.words {
-                    let skribbl_state = SkribblState::new(
-                        self.sessions.keys().cloned().collect::<Vec<Username>>(),
-                        words.clone(),
-                    );
-                    self.game_state = GameState::Skribbl(skribbl_state.clone());
-                    self.broadcast(ToClientMsg::SkribblStateChanged(skribbl_state))
-                        .await?;
-                }
-            }
+            GameState::Lobby(_) | GameState::FreeDraw => {}
         }
 
-        if should_broadcast {
+        if should_broadcast && self.muted.contains(&username) {
+            self.send_system_msg(&username, "You are muted".to_string())
+                .await?;
+        } else if should_broadcast {
             self.broadcast(ToClientMsg::NewMessage(msg)).await?;
         }
 
@@ -218,14 +584,57 @@ impl ServerState {
             ToServerMsg::NewMessage(message) => {
                 self.on_new_message(username, message).await?;
             }
-            ToServerMsg::NewLine(line) => {
-                self.lines.push(line);
-                self.broadcast(ToClientMsg::NewLine(line)).await?;
+            ToServerMsg::Stroke(batch) => {
+                if !batch.points.is_empty() {
+                    self.on_draw_op(&username, DrawOp::Stroke(batch));
+                }
+            }
+            ToServerMsg::Fill(fill) => {
+                self.on_draw_op(&username, DrawOp::Fill(fill));
+            }
+            // in a game, images would make drawing the word a bit too easy
+            ToServerMsg::Image(image) => {
+                if let GameState::FreeDraw = self.game_state {
+                    self.on_draw_op(&username, DrawOp::Image(image));
+                }
+            }
+            ToServerMsg::Undo => {
+                self.on_undo(&username).await?;
+            }
+            ToServerMsg::Redo => {
+                self.on_redo(&username).await?;
             }
             ToServerMsg::ClearCanvas => {
-                self.lines.clear();
+                if !self.may_draw(&username) {
+                    return Ok(());
+                }
+                self.export_drawing(None);
+                self.clear_canvas();
                 self.broadcast(ToClientMsg::ClearCanvas).await?;
             }
+            ToServerMsg::ChooseWord(idx) => {
+                self.on_choose_word(&username, idx).await?;
+            }
+            // the handshake is over at this point
+            ToServerMsg::Hello(_) => {}
+        }
+        Ok(())
+    }
+
+    /// remove the players whose time to reconnect is over from the room
+    async fn expire_disconnected_players(&mut self) -> Result<()> {
+        let now = get_time_now();
+        let expired = self
+            .disconnected
+            .iter()
+            .filter(|(_, player)| player.expires_at <= now)
+            .map(|(username, _)| username.clone())
+            .collect::<Vec<_>>();
+        for username in expired {
+            self.disconnected.remove(&username);
+            self.remove_player(&username).await?;
+            self.broadcast_system_msg(format!("{} left", username))
+                .await?;
         }
         Ok(())
     }
@@ -239,25 +648,22 @@ impl ServerState {
         let remaining_time = state.remaining_time();
         let revealed_char_cnt = state.revealed_characters().len();
 
-        if remaining_time <= 0 {
-            let old_word = state.current_word().to_string();
+        if remaining_time == 0 && state.is_choosing_word() {
+            // the drawing user took too long, so just pick the first word for them
+            state.choose_word(0);
+            let state = SkribblState::clone(state);
+            self.broadcast(ToClientMsg::SkribblStateChanged(state))
+                .await?;
+        } else if remaining_time == 0 {
             if let Some(ref mut drawing_user) = state.player_states.get_mut(&state.drawing_user) {
-                drawing_user.score += 50;
+                drawing_user.score += state.settings.drawer_bonus;
             }
-
-            state.next_turn();
-            let state = self.game_state.skribbl_state().unwrap().clone();
-            self.lines.clear();
-            tokio::try_join!(
-                self.broadcast(ToClientMsg::SkribblStateChanged(state)),
-                self.broadcast(ToClientMsg::ClearCanvas),
-                self.broadcast_system_msg(format!("The word was: \"{}\"", old_word)),
-            )?;
-        } else if remaining_time <= (ROUND_DURATION / 4) as u32 && revealed_char_cnt < 2
-            || remaining_time <= (ROUND_DURATION / 2) as u32 && revealed_char_cnt < 1
+            self.end_turn().await?;
+        } else if !state.is_choosing_word()
+            && revealed_char_cnt < state.settings.hints_due(remaining_time)
         {
             state.reveal_random_char();
-            let state = state.clone();
+            let state = SkribblState::clone(state);
             self.broadcast(ToClientMsg::SkribblStateChanged(state))
                 .await?;
         }
@@ -268,25 +674,116 @@ impl ServerState {
         Ok(())
     }
 
-    pub async fn on_user_joined(&mut self, session: UserSession) -> Result<()> {
-        if let GameState::Skribbl(ref mut state) = self.game_state {
-            state.add_player(session.username.clone());
-            let state = state.clone();
-            tokio::try_join!(
-                self.broadcast(ToClientMsg::SkribblStateChanged(state)),
-                self.broadcast_system_msg(format!("{} joined", session.username)),
-            )?;
+    pub async fn on_user_joined(
+        &mut self,
+        mut session: UserSession,
+        resume_token: Option<String>,
+    ) -> Result<JoinResult> {
+        let username = session.username.clone();
+        if self.banned.contains(&username) {
+            return Ok(Err(JoinRejectReason::Banned));
+        }
+
+        let mut is_resumed = false;
+        if let Some(resume_token) = resume_token {
+            let is_resumable = |token: &str| token == resume_token;
+            let has_live_session = matches!(
+                self.sessions.get(&username),
+                Some(old) if is_resumable(&old.resume_token)
+            );
+            if has_live_session {
+                // the old connection didn't notice it's dead yet, so just replace it
+                if let Some(old_session) = self.sessions.remove(&username) {
+                    let _ = old_session.close().await;
+                }
+                session.resume_token = resume_token;
+                self.send_initial_state(&session).await?;
+                self.sessions.insert(username.clone(), session);
+                self.resend_word_choices(&username).await?;
+                return Ok(Ok(()));
+            }
+            match self.disconnected.remove(&username) {
+                Some(player) if is_resumable(&player.resume_token) => {
+                    is_resumed = true;
+                    session.resume_token = resume_token;
+                }
+                Some(player) => {
+                    self.disconnected.insert(username, player);
+                    return Ok(Err(JoinRejectReason::SessionExpired));
+                }
+                None => return Ok(Err(JoinRejectReason::SessionExpired)),
+            }
+        } else if self.sessions.contains_key(&username) || self.disconnected.contains_key(&username)
+        {
+            return Ok(Err(JoinRejectReason::UsernameTaken));
+        }
+
+        let is_configured_host = self.game_opts.host.as_ref() == Some(&username);
+        if (self.host.is_none() || is_configured_host) && self.host.as_ref() != Some(&username) {
+            self.set_host(Some(username.clone())).await?;
+        }
+
+        // players that come back are usually still in the game, in which case adding them changes nothing
+        let joined_msg = if is_resumed {
+            format!("{} reconnected", session.username)
+        } else {
+            format!("{} joined", session.username)
+        };
+        match &mut self.game_state {
+            GameState::Skribbl(state) => {
+                state.add_player(session.username.clone(), PlayerState::default());
+                let state = SkribblState::clone(state);
+                tokio::try_join!(
+                    self.broadcast(ToClientMsg::SkribblStateChanged(state)),
+                    self.broadcast_system_msg(joined_msg),
+                )?;
+            }
+            GameState::Lobby(state) => {
+                state.add_player(session.username.clone());
+                let state = state.clone();
+                tokio::try_join!(
+                    self.broadcast(ToClientMsg::LobbyStateChanged(state)),
+                    self.broadcast_system_msg(joined_msg),
+                )?;
+            }
+            GameState::FreeDraw => {
+                if is_resumed {
+                    self.broadcast_system_msg(joined_msg).await?;
+                }
+            }
         }
 
+        self.send_initial_state(&session).await?;
+        self.sessions.insert(username.clone(), session);
+        if is_resumed {
+            self.resend_word_choices(&username).await?;
+        }
+        Ok(Ok(()))
+    }
+
+    /// the words a drawing user was offered went to their old connection, so offer them again
+    async fn resend_word_choices(&self, username: &Username) -> Result<()> {
+        match self.game_state.skribbl_state() {
+            Some(state) if state.is_drawing(username) => self.send_word_choices().await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn send_initial_state(&mut self, session: &UserSession) -> Result<()> {
+        // the new session isn't in `sessions` yet, so it would miss the operations that weren't sent out yet
+        self.flush_draw_ops().await?;
         let initial_state = InitialState {
-            lines: self.lines.clone(),
+            canvas: self.canvas.base().clone(),
+            ops: self.canvas.ops().to_vec(),
             skribbl_state: self.game_state.skribbl_state().cloned(),
-            dimensions: self.dimensions,
+            lobby_state: self.game_state.lobby_state().cloned(),
+            host: self.host.clone(),
+            messages: self.chat_history.lock().await.iter().cloned().collect(),
+            resume_token: session.resume_token.clone(),
         };
         session
             .send(ToClientMsg::InitialState(initial_state))
             .await?;
-        self.sessions.insert(session.username.clone(), session);
         Ok(())
     }
 
@@ -297,6 +794,13 @@ impl ServerState {
         Ok(())
     }
 
+    /// send a Message::SystemMsg to a specific session
+    async fn send_system_msg(&self, user: &Username, msg: String) -> Result<()> {
+        self.send_to(user, ToClientMsg::NewMessage(Message::SystemMsg(msg)))
+            .await?;
+        Ok(())
+    }
+
     /// send a ToClientMsg to a specific session
     pub async fn send_to(&self, user: &Username, msg: ToClientMsg) -> Result<()> {
         self.sessions
@@ -308,84 +812,410 @@ impl ServerState {
     }
 
     /// broadcast a ToClientMsg to all running sessions
+    /// sessions whose connection is gone are skipped, they'll be removed once their connection closes.
     async fn broadcast(&self, msg: ToClientMsg) -> Result<()> {
-        futures_util::future::try_join_all(
+        if let ToClientMsg::NewMessage(message) = &msg {
+            let mut chat_history = self.chat_history.lock().await;
+            chat_history.push_back(message.clone());
+            if chat_history.len() > CHAT_HISTORY_LENGTH {
+                chat_history.pop_front();
+            }
+        }
+        futures_util::future::join_all(
             self.sessions
                 .iter()
                 .map(|(_, session)| session.send(msg.clone())),
         )
-        .await?;
+        .await;
         Ok(())
     }
 
-    /// run the main server, reacting to any server events
-    async fn run(&mut self, mut evt_recv: tokio::sync::mpsc::Receiver<ServerEvent>) -> Result<()> {
+    /// draw on the canvas if the player may draw, and queue the operation to be sent out with the next flush
+    fn on_draw_op(&mut self, username: &Username, op: DrawOp) {
+        if !self.may_draw(username) || !op.fits(self.canvas.pixels().dimensions()) {
+            return;
+        }
+        self.canvas.apply(op.clone());
+        data::push_op(&mut self.pending_ops, op);
+        self.undone_ops.clear();
+    }
+
+    /// whether the given player may currently draw on the canvas
+    fn may_draw(&self, username: &Username) -> bool {
+        match &self.game_state {
+            GameState::Skribbl(state) => state.is_drawing(username),
+            _ => true,
+        }
+    }
+
+    /// take back the most recent stroke or fill
+    async fn on_undo(&mut self, username: &Username) -> Result<()> {
+        if !self.may_draw(username) {
+            return Ok(());
+        }
+        let id = match self.canvas.last_id() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        // make sure everyone has the whole stroke before it's taken back
+        self.flush_draw_ops().await?;
+        let undone = self.canvas.remove(id);
+        self.undone_ops.push(undone);
+        self.broadcast(ToClientMsg::Undo(id)).await
+    }
+
+    /// put back the most recently undone stroke or fill
+    async fn on_redo(&mut self, username: &Username) -> Result<()> {
+        if !self.may_draw(username) {
+            return Ok(());
+        }
+        if let Some(redone) = self.undone_ops.pop() {
+            self.flush_draw_ops().await?;
+            for op in redone.iter() {
+                self.canvas.apply(op.clone());
+            }
+            self.broadcast(ToClientMsg::Redo(redone)).await?;
+        }
+        Ok(())
+    }
+
+    /// send out everything drawn since the last flush
+    async fn flush_draw_ops(&mut self) -> Result<()> {
+        if self.pending_ops.is_empty() {
+            return Ok(());
+        }
+        let ops = std::mem::take(&mut self.pending_ops);
+        self.broadcast(ToClientMsg::DrawOps(ops)).await
+    }
+
+    /// save the drawing to the export directory, if one is configured and anything was drawn.
+    /// the files are named after the room, the time, a counter and the word that was drawn,
+    /// and written in the background so the room isn't held up by the file system.
+    fn export_drawing(&mut self, word: Option<&str>) {
+        let export_dir = match &self.game_opts.export_dir {
+            Some(export_dir) if !self.canvas.pixels().is_empty() => export_dir.clone(),
+            _ => return,
+        };
+        self.saved_drawings += 1;
+        let mut name = format!("{}-{}-{}", self.room, get_time_now(), self.saved_drawings);
+        if let Some(word) = word {
+            name = format!("{}-{}", name, word);
+        }
+        let name = export::file_name(&name);
+        let canvas = self.canvas.clone();
+        tokio::task::spawn_blocking(move || {
+            for extension in &["png", "svg"] {
+                let path = export_dir.join(format!("{}.{}", name, extension));
+                match export::save(&canvas, &path, export::DEFAULT_SCALE) {
+                    Ok(()) => info!("saved drawing to {}", path.display()),
+                    Err(err) => warn!("could not save drawing to {}: {}", path.display(), err),
+                }
+            }
+        });
+    }
+
+    /// save the drawing of a turn to the gallery directory in the background,
+    /// if one is configured and anything was drawn
+    fn save_to_gallery(&mut self, snapshot: Snapshot) {
+        if snapshot.canvas.is_empty() {
+            return;
+        }
+        if let Some(gallery_dir) = self.game_opts.gallery_dir.clone() {
+            self.saved_drawings += 1;
+            let number = self.saved_drawings;
+            tokio::task::spawn_blocking(move || match snapshot.save(&gallery_dir, number) {
+                Ok(path) => info!("saved drawing to {}", path.display()),
+                Err(err) => warn!("could not save drawing to the gallery: {}", err),
+            });
+        }
+    }
+
+    /// remove everything from the canvas, including operations that weren't sent out yet
+    fn clear_canvas(&mut self) {
+        self.canvas.clear();
+        self.undone_ops.clear();
+        self.pending_ops.clear();
+    }
+
+    /// run the main server, reacting to any server events.
+    /// returns once nobody is in the room anymore and nobody can come back to it,
+    /// after closing it in `rooms`.
+    async fn run(
+        &mut self,
+        mut evt_recv: tokio::sync::mpsc::Receiver<ServerEvent>,
+        rooms: &Rooms,
+        id: RoomId,
+    ) -> Result<()> {
+        let mut flush_interval = tokio::time::interval(DRAW_FLUSH_INTERVAL);
         loop {
-            if let Some(evt) = evt_recv.recv().await {
-                match evt {
-                    ServerEvent::ToServerMsg(name, msg) => {
-                        self.on_to_srv_msg(name.into(), msg).await?
+            let evt = tokio::select! {
+                evt = evt_recv.recv() => match evt {
+                    Some(evt) => evt,
+                    None => break,
+                },
+                _ = flush_interval.tick() => {
+                    self.flush_draw_ops().await?;
+                    // players have to be removed even if nobody is left to send ticks
+                    self.expire_disconnected_players().await?;
+                    let is_abandoned = self.sessions.is_empty() && self.disconnected.is_empty();
+                    if is_abandoned && rooms.close_if_empty(&self.room, id).await {
+                        break;
                     }
-                    ServerEvent::UserJoined(session) => self.on_user_joined(session).await?,
-                    ServerEvent::UserLeft(username) => self.remove_player(&username).await?,
-                    ServerEvent::Tick => self.on_tick().await?,
+                    continue;
+                }
+            };
+            match evt {
+                ServerEvent::ToServerMsg(name, msg) => self.on_to_srv_msg(name, msg).await?,
+                ServerEvent::UserJoined(request) => {
+                    let join_result = self
+                        .on_user_joined(request.session, request.resume_token)
+                        .await?;
+                    let _ = request.result_send.send(join_result);
+                }
+                ServerEvent::UserLeft(username, session_id) => {
+                    self.on_user_left(&username, session_id).await?
+                }
+                ServerEvent::Tick => self.on_tick().await?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// identifies a room, as a room that was closed can be opened again under the same name
+type RoomId = u64;
+
+static NEXT_ROOM_ID: AtomicU64 = AtomicU64::new(0);
+
+/// a running room, together with the number of connections currently in it
+#[derive(Debug)]
+struct Room {
+    id: RoomId,
+    evt_send: tokio::sync::mpsc::Sender<ServerEvent>,
+    connections: usize,
+}
+
+/// all rooms hosted by this server, indexed by their name.
+/// rooms are created when the first client asks for them and closed by their server task
+/// once the last player left and can't reconnect anymore.
+#[derive(Clone)]
+struct Rooms {
+    rooms: Arc<Mutex<HashMap<String, Room>>>,
+    game_opts: GameOpts,
+}
+
+impl Rooms {
+    fn new(game_opts: GameOpts) -> Self {
+        Rooms {
+            rooms: Arc::new(Mutex::new(HashMap::new())),
+            game_opts,
+        }
+    }
+
+    /// join the room with the given name, creating it if it doesn't exist yet.
+    /// returns the id of the room and the sender for its server events.
+    async fn join(&self, name: &str) -> (RoomId, tokio::sync::mpsc::Sender<ServerEvent>) {
+        let mut rooms = self.rooms.lock().await;
+        let room = rooms.entry(name.to_string()).or_insert_with(|| {
+            info!("creating room {}", name);
+            let id = NEXT_ROOM_ID.fetch_add(1, Ordering::Relaxed);
+            let mut server_state = ServerState::new(name.to_string(), self.game_opts.clone());
+            let (evt_send, evt_recv) = tokio::sync::mpsc::channel::<ServerEvent>(ROOM_QUEUE_LENGTH);
+            let rooms = self.clone();
+            let name = name.to_string();
+            tokio::spawn(async move {
+                if let Err(err) = server_state.run(evt_recv, &rooms, id).await {
+                    warn!("room {} stopped: {:?}", name, err);
+                    rooms.remove(&name, id).await;
                 }
+            });
+            Room {
+                id,
+                evt_send,
+                connections: 0,
+            }
+        });
+        room.connections += 1;
+        (room.id, room.evt_send.clone())
+    }
+
+    /// leave the room with the given name.
+    /// the room stays open, so players who lost their connection can still come back to it.
+    async fn leave(&self, name: &str, id: RoomId) {
+        let mut rooms = self.rooms.lock().await;
+        if let Some(room) = rooms.get_mut(name).filter(|room| room.id == id) {
+            room.connections -= 1;
+        }
+    }
+
+    /// close the given room unless someone is connected to it or about to join it,
+    /// returning whether it is closed now
+    async fn close_if_empty(&self, name: &str, id: RoomId) -> bool {
+        let mut rooms = self.rooms.lock().await;
+        match rooms.get(name) {
+            Some(room) if room.id == id && room.connections > 0 => false,
+            Some(room) if room.id == id => {
+                info!("closing room {}", name);
+                rooms.remove(name);
+                true
             }
+            // nobody can get to the room anymore
+            _ => true,
+        }
+    }
+
+    /// remove a room whose server task stopped, so the next client asking for it gets a new one
+    async fn remove(&self, name: &str, id: RoomId) {
+        let mut rooms = self.rooms.lock().await;
+        if matches!(rooms.get(name), Some(room) if room.id == id) {
+            rooms.remove(name);
         }
     }
 }
 
 pub async fn run_server(opt: CliOpts) -> Result<()> {
     let addr = format!("0.0.0.0:{}", opt.port);
-    let dimensions = opt.dimensions;
-    let maybe_words = opt.words;
 
     let mut server_listener = TcpListener::bind(addr)
         .await
         .expect("Could not start webserver (could not bind)");
 
-    let (srv_event_send, srv_event_recv) = tokio::sync::mpsc::channel::<ServerEvent>(1);
-    let mut server_state = ServerState::new(GameState::FreeDraw, dimensions, maybe_words);
-
-    tokio::spawn(async move {
-        server_state.run(srv_event_recv).await.unwrap();
-    });
+    let rooms = Rooms::new(opt.into());
 
     while let Ok((stream, _)) = server_listener.accept().await {
         let peer = stream.peer_addr().expect("Peer didn't have an address");
-        tokio::spawn(handle_connection(peer, stream, srv_event_send.clone()));
+        tokio::spawn(handle_connection(peer, stream, rooms.clone()));
     }
     Ok(())
 }
 
-async fn handle_connection(
-    peer: SocketAddr,
-    stream: TcpStream,
-    mut srv_event_send: tokio::sync::mpsc::Sender<ServerEvent>,
-) -> Result<()> {
-    let ws_stream = tokio_tungstenite::accept_async(stream).await?;
-    println!("new WebSocket connection: {}", peer);
+async fn handle_connection(peer: SocketAddr, stream: TcpStream, rooms: Rooms) -> Result<()> {
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        max_frame_size: Some(MAX_MESSAGE_SIZE),
+        ..WebSocketConfig::default()
+    };
+    let ws_stream = tokio_tungstenite::accept_async_with_config(stream, Some(config)).await?;
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
 
-    // first, wait for the client to send his username
-    let username: Username = loop {
-        let msg = ws_receiver
-            .next()
-            .await
-            .expect("No username message received")?;
-        if let tungstenite::Message::Text(username) = msg {
-            break username.into();
+    // first, wait for the client to introduce itself
+    let hello = loop {
+        let msg = match ws_receiver.next().await {
+            Some(msg) => msg?,
+            None => return Ok(()),
+        };
+        if let tungstenite::Message::Text(msg) = msg {
+            match serde_json::from_str(&msg) {
+                Ok(ToServerMsg::Hello(hello)) => break hello,
+                // most likely a client from before the handshake existed, which just sends its username
+                _ => {
+                    let reason = JoinRejectReason::IncompatibleVersion {
+                        client_version: 0,
+                        server_version: PROTOCOL_VERSION,
+                    };
+                    return reject_join(&mut ws_sender, reason).await;
+                }
+            }
         }
     };
+    if hello.protocol_version != PROTOCOL_VERSION {
+        let reason = JoinRejectReason::IncompatibleVersion {
+            client_version: hello.protocol_version,
+            server_version: PROTOCOL_VERSION,
+        };
+        return reject_join(&mut ws_sender, reason).await;
+    }
+
+    let room_name = hello
+        .room
+        .clone()
+        .unwrap_or_else(|| DEFAULT_ROOM.to_string());
+    info!(
+        "new WebSocket connection: {} ({} {}, room {})",
+        peer, hello.client_name, hello.client_version, room_name
+    );
+
+    let (room_id, srv_event_send) = rooms.join(&room_name).await;
+    let result = run_session(
+        ws_sender,
+        ws_receiver,
+        hello,
+        room_name.clone(),
+        srv_event_send,
+    )
+    .await;
+    rooms.leave(&room_name, room_id).await;
+    result
+}
+
+/// tell the client why it can't join and close the connection
+async fn reject_join(
+    ws_sender: &mut SplitSink<WebSocketStream<TcpStream>, tungstenite::Message>,
+    reason: JoinRejectReason,
+) -> Result<()> {
+    let close_frame = CloseFrame {
+        code: CloseCode::Policy,
+        reason: reason.to_string().into(),
+    };
+    let msg = ToClientMsg::JoinRejected(reason);
+    ws_sender.send(Encoding::Json.encode(&msg)).await?;
+    ws_sender
+        .send(tungstenite::Message::Close(Some(close_frame)))
+        .await?;
+    Ok(())
+}
+
+async fn run_session(
+    mut ws_sender: SplitSink<WebSocketStream<TcpStream>, tungstenite::Message>,
+    mut ws_receiver: SplitStream<WebSocketStream<TcpStream>>,
+    hello: Hello,
+    room_name: String,
+    mut srv_event_send: tokio::sync::mpsc::Sender<ServerEvent>,
+) -> Result<()> {
+    let username = hello.username;
+    if let Err(reason) = username.validate() {
+        return reject_join(&mut ws_sender, reason).await;
+    }
 
-    let (session_msg_send, mut session_msg_recv) = tokio::sync::mpsc::channel(1);
+    let capabilities: Vec<Capability> = hello
+        .capabilities
+        .into_iter()
+        .filter(|capability| SERVER_CAPABILITIES.contains(capability))
+        .collect();
+    let resume_token = hello
+        .resume_token
+        .filter(|_| capabilities.contains(&Capability::Resume));
+
+    let (session_msg_send, mut session_msg_recv) = tokio::sync::mpsc::channel(SESSION_QUEUE_LENGTH);
     let (session_close_send, mut session_close_recv) = tokio::sync::mpsc::channel(1);
 
-    // then, create a session and send that session to the server's main thread
+    // then, create a session and send that session to the server's main thread,
+    // waiting for it to decide whether we may join
     let session = UserSession::new(username.clone(), session_msg_send, session_close_send);
+    let session_id = session.id;
+    let (result_send, join_result_recv) = oneshot::channel();
     srv_event_send
-        .send(ServerEvent::UserJoined(session))
+        .send(ServerEvent::UserJoined(JoinRequest {
+            session,
+            resume_token,
+            result_send,
+        }))
         .await?;
+    match join_result_recv.await {
+        Ok(Ok(())) => {}
+        Ok(Err(reason)) => return reject_join(&mut ws_sender, reason).await,
+        Err(_) => return Ok(()),
+    }
+
+    // we're in, so greet the client before the queued initial state is sent
+    let encoding = Encoding::from_capabilities(&capabilities);
+    let welcome = ToClientMsg::Welcome(Welcome {
+        protocol_version: PROTOCOL_VERSION,
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        room: room_name,
+        capabilities,
+    });
+    ws_sender.send(Encoding::Json.encode(&welcome)).await?;
 
     // TODO look at stream forwarding for this...
     // asynchronously read messages that the main server thread wants
@@ -395,8 +1225,7 @@ async fn handle_connection(
             tokio::select! {
                 maybe_msg = session_msg_recv.recv() => match maybe_msg {
                     Some(msg) => {
-                        let msg = serde_json::to_string(&msg).expect("Could not serialize msg");
-                        let result = ws_sender.send(tungstenite::Message::Text(msg)).await;
+                        let result = ws_sender.send(encoding.encode(&msg)).await;
                         if let Err(_) = result {
                             break result;
                         }
@@ -425,27 +1254,29 @@ async fn handle_connection(
 
             // Websocket messages from the client
             msg = ws_receiver.next() => match msg {
-                Some(Ok(tungstenite::Message::Text(msg))) => match serde_json::from_str(&msg) {
-                    Ok(Some(msg)) => {
+                Some(Ok(tungstenite::Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(msg)) => match Encoding::decode(&msg) {
+                    Some(Ok(Some(msg))) => {
                         srv_event_send
                             .send(ServerEvent::ToServerMsg(username.clone(), msg))
                             .await?;
                     }
-                    Ok(None) => {
+                    Some(Ok(None)) => {
                         break;
                     }
-                    Err(err) => {
-                        eprintln!("{} (msg was: {})", err, msg);
+                    Some(Err(err)) => {
+                        warn!("{} (msg was: {:?})", err, msg);
                     }
+                    None => {}
                 },
-                Some(Ok(tungstenite::Message::Close(_))) | Some(Err(_)) | None => break,
-                _ => {}
             }
         }
     }
 
     drop(send_thread);
-    srv_event_send.send(ServerEvent::UserLeft(username)).await?;
+    srv_event_send
+        .send(ServerEvent::UserLeft(username, session_id))
+        .await?;
     Ok(())
 }
 
@@ -480,3 +1311,232 @@ fn levenshtein_distance(a: String, b: String) -> usize {
     }
     matrix[b_len - 1][a_len - 1]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::{Coord, StrokeBatch},
+        server::skribbl::GameSettings,
+    };
+    use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+    const ROOM: &str = "room";
+
+    fn username() -> Username {
+        Username::from("someone".to_string())
+    }
+
+    fn game_opts() -> GameOpts {
+        GameOpts {
+            dimensions: (10, 10),
+            words: Vec::new(),
+            settings: GameSettings::new(60, 3),
+            min_players: 2,
+            host: None,
+            export_dir: None,
+            gallery_dir: None,
+        }
+    }
+
+    fn rooms() -> Rooms {
+        Rooms::new(game_opts())
+    }
+
+    /// a connection to a room, set up the way `handle_connection` does it
+    struct Connection {
+        room_id: RoomId,
+        evt_send: Sender<ServerEvent>,
+        session_id: SessionId,
+        msg_recv: Receiver<ToClientMsg>,
+        _close_recv: Receiver<()>,
+    }
+
+    impl Connection {
+        async fn open(rooms: &Rooms, resume_token: Option<String>) -> (Connection, JoinResult) {
+            let (room_id, mut evt_send) = rooms.join(ROOM).await;
+            let (msg_send, msg_recv) = channel(SESSION_QUEUE_LENGTH);
+            let (close_send, close_recv) = channel(1);
+            let session = UserSession::new(username(), msg_send, close_send);
+            let session_id = session.id;
+            let (result_send, result_recv) = oneshot::channel();
+            evt_send
+                .send(ServerEvent::UserJoined(JoinRequest {
+                    session,
+                    resume_token,
+                    result_send,
+                }))
+                .await
+                .unwrap();
+            let connection = Connection {
+                room_id,
+                evt_send,
+                session_id,
+                msg_recv,
+                _close_recv: close_recv,
+            };
+            (connection, result_recv.await.unwrap())
+        }
+
+        async fn initial_state(&mut self) -> InitialState {
+            loop {
+                if let Some(ToClientMsg::InitialState(state)) = self.msg_recv.recv().await {
+                    return state;
+                }
+            }
+        }
+
+        async fn close(mut self, rooms: &Rooms) {
+            let username = username();
+            let left = ServerEvent::UserLeft(username, self.session_id);
+            self.evt_send.send(left).await.unwrap();
+            rooms.leave(ROOM, self.room_id).await;
+        }
+    }
+
+    async fn open_room_id(rooms: &Rooms) -> Option<RoomId> {
+        rooms.rooms.lock().await.get(ROOM).map(|room| room.id)
+    }
+
+    #[tokio::test]
+    async fn the_last_player_can_resume_within_the_grace_period() {
+        let rooms = rooms();
+        let (mut connection, result) = Connection::open(&rooms, None).await;
+        assert!(result.is_ok());
+        let resume_token = connection.initial_state().await.resume_token;
+        let stroke = StrokeBatch::new(1, None, 1, Coord(2, 2));
+        let draw = ServerEvent::ToServerMsg(username(), ToServerMsg::Stroke(stroke));
+        connection.evt_send.send(draw).await.unwrap();
+        let room_id = connection.room_id;
+        connection.close(&rooms).await;
+
+        tokio::time::delay_for(DRAW_FLUSH_INTERVAL * 5).await;
+        assert_eq!(open_room_id(&rooms).await, Some(room_id));
+
+        let (mut connection, result) = Connection::open(&rooms, Some(resume_token)).await;
+        assert!(result.is_ok());
+        assert_eq!(connection.room_id, room_id);
+        let state = connection.initial_state().await;
+        assert_eq!(state.ops.len(), 1);
+        assert_eq!(state.host, Some(username()));
+    }
+
+    #[tokio::test]
+    async fn rooms_nobody_can_come_back_to_are_closed() {
+        let rooms = rooms();
+        let (room_id, _) = rooms.join(ROOM).await;
+        rooms.leave(ROOM, room_id).await;
+
+        tokio::time::delay_for(DRAW_FLUSH_INTERVAL * 5).await;
+        assert_eq!(open_room_id(&rooms).await, None);
+    }
+
+    /// a room state with a connected host, and the receiver of the messages sent to them
+    fn state_with_host() -> (ServerState, Receiver<ToClientMsg>) {
+        let mut state = ServerState::new(ROOM.to_string(), game_opts());
+        let (msg_send, msg_recv) = channel(SESSION_QUEUE_LENGTH);
+        let (close_send, _) = channel(1);
+        let session = UserSession::new(username(), msg_send, close_send);
+        state.sessions.insert(username(), session);
+        state.host = Some(username());
+        (state, msg_recv)
+    }
+
+    fn disconnected_player() -> DisconnectedPlayer {
+        DisconnectedPlayer {
+            resume_token: "token".to_string(),
+            expires_at: get_time_now() + RECONNECT_GRACE_PERIOD,
+        }
+    }
+
+    #[tokio::test]
+    async fn moderating_someone_who_isnt_in_the_room_only_tells_the_host() {
+        let (mut state, mut msg_recv) = state_with_host();
+        let nobody = Username::from("nobody".to_string());
+        for command in vec![
+            CommandMsg::KickPlayer(nobody.clone()),
+            CommandMsg::BanPlayer(nobody.clone()),
+            CommandMsg::MutePlayer(nobody.clone()),
+            CommandMsg::UnmutePlayer(nobody.clone()),
+        ] {
+            state.on_command_msg(&username(), &command).await.unwrap();
+        }
+        assert!(state.banned.is_empty());
+        assert!(state.muted.is_empty());
+
+        let mut replies = Vec::new();
+        while let Ok(msg) = msg_recv.try_recv() {
+            match msg {
+                ToClientMsg::NewMessage(Message::SystemMsg(msg)) => replies.push(msg),
+                msg => panic!("unexpected message {:?}", msg),
+            }
+        }
+        assert_eq!(
+            replies,
+            vec![
+                "There is no player called nobody",
+                "There is no player called nobody",
+                "There is no player called nobody",
+                "nobody isn't muted",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn players_who_lost_their_connection_can_be_moderated() {
+        let (mut state, _msg_recv) = state_with_host();
+        let other = Username::from("other".to_string());
+        state
+            .disconnected
+            .insert(other.clone(), disconnected_player());
+        let mute = CommandMsg::MutePlayer(other.clone());
+        state.on_command_msg(&username(), &mute).await.unwrap();
+        assert!(state.muted.contains(&other));
+        let ban = CommandMsg::BanPlayer(other.clone());
+        state.on_command_msg(&username(), &ban).await.unwrap();
+        assert!(state.banned.contains(&other));
+        assert!(!state.is_in_room(&other));
+    }
+
+    #[tokio::test]
+    async fn restarting_keeps_players_who_lost_their_connection() {
+        let (mut state, _msg_recv) = state_with_host();
+        state.game_opts.words = vec!["apple".to_string()];
+        let other = Username::from("other".to_string());
+        state
+            .disconnected
+            .insert(other.clone(), disconnected_player());
+        let restart = CommandMsg::RestartGame;
+        state.on_command_msg(&username(), &restart).await.unwrap();
+        let players = &state.game_state.skribbl_state().unwrap().player_states;
+        assert!(players.contains_key(&username()));
+        assert!(players.contains_key(&other));
+    }
+
+    #[tokio::test]
+    async fn a_drawer_who_resumes_is_offered_the_words_again() {
+        let mut state = ServerState::new(ROOM.to_string(), game_opts());
+        let words = vec![
+            "apple".to_string(),
+            "banana".to_string(),
+            "cherry".to_string(),
+        ];
+        let skribbl_state = SkribblState::new(vec![username()], words, GameSettings::new(60, 3));
+        state.game_state = GameState::Skribbl(Box::new(skribbl_state));
+        state.disconnected.insert(username(), disconnected_player());
+
+        let (msg_send, mut msg_recv) = channel(SESSION_QUEUE_LENGTH);
+        let (close_send, _close_recv) = channel(1);
+        let session = UserSession::new(username(), msg_send, close_send);
+        let result = state.on_user_joined(session, Some("token".to_string()));
+        assert!(result.await.unwrap().is_ok());
+
+        let mut offered_words = Vec::new();
+        while let Ok(msg) = msg_recv.try_recv() {
+            if let ToClientMsg::WordChoices(words) = msg {
+                offered_words = words;
+            }
+        }
+        assert_eq!(offered_words.len(), 3);
+    }
+}
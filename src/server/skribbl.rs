@@ -1,16 +1,92 @@
-use super::server::ROUND_DURATION;
 use crate::client::Username;
 use rand::{prelude::IteratorRandom, seq::SliceRandom};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::{cmp::max, time};
+use std::{
+    cmp::{max, min},
+    time,
+};
 use time::{SystemTime, UNIX_EPOCH};
 
+/// settings of a single game, determining its timings and scoring
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GameSettings {
+    /// duration of a single turn in seconds
+    pub round_duration: u64,
+
+    /// the number of rounds after which the game ends
+    pub number_of_rounds: usize,
+
+    /// remaining times (in seconds) at which another character of the word gets revealed
+    pub hint_times: Vec<u32>,
+
+    /// score every player gets for solving the word
+    pub solve_score: u32,
+
+    /// additional score for solving, scaled by the fraction of the turn that was remaining
+    pub max_time_bonus: u32,
+
+    /// score the drawing player gets at the end of their turn
+    pub drawer_bonus: u32,
+
+    /// number of words the drawing player can choose from at the start of their turn
+    pub word_choice_count: usize,
+
+    /// time (in seconds) the drawing player has to choose a word before one is picked for them
+    pub word_choice_duration: u64,
+}
+
+impl GameSettings {
+    /// settings for the given timings, revealing hints at half and a quarter of the turn's duration.
+    pub fn new(round_duration: u64, number_of_rounds: usize) -> Self {
+        let round_duration = max(1, round_duration);
+        GameSettings {
+            round_duration,
+            number_of_rounds: max(1, number_of_rounds),
+            hint_times: vec![(round_duration / 2) as u32, (round_duration / 4) as u32],
+            solve_score: 50,
+            max_time_bonus: 50,
+            drawer_bonus: 50,
+            word_choice_count: 3,
+            word_choice_duration: 15,
+        }
+    }
+
+    /// how many characters should be revealed when the given time is remaining
+    pub fn hints_due(&self, remaining_time: u32) -> usize {
+        self.hint_times
+            .iter()
+            .filter(|&&hint_time| remaining_time <= hint_time)
+            .count()
+    }
+
+    /// the score a player gets for solving the word with the given time remaining
+    pub fn calculate_score_increase(&self, remaining_time: u32) -> u32 {
+        let remaining_fraction = remaining_time as f64 / self.round_duration as f64;
+        self.solve_score + (remaining_fraction * self.max_time_bonus as f64) as u32
+    }
+}
+
+/// the phases every turn goes through
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TurnPhase {
+    /// the drawing user is choosing which word to draw
+    ChoosingWord,
+    /// the word is chosen and the others are guessing
+    Drawing,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SkribblState {
     current_word: String,
     revealed_characters: Vec<usize>,
 
+    /// the words the drawing user can choose from. only known to the server.
+    #[serde(skip)]
+    word_choices: Vec<String>,
+
+    pub turn_phase: TurnPhase,
+
     /// the currently drawing user
     pub drawing_user: Username,
 
@@ -23,6 +99,11 @@ pub struct SkribblState {
     pub round_end_time: u64,
 
     pub remaining_words: Vec<String>,
+
+    /// the current round, starting at 1. a round is over once everyone had their turn drawing.
+    pub round: usize,
+
+    pub settings: GameSettings,
 }
 
 impl SkribblState {
@@ -39,6 +120,43 @@ impl SkribblState {
         self.revealed_characters = Vec::new();
     }
 
+    pub fn word_choices(&self) -> &[String] {
+        self.word_choices.as_ref()
+    }
+
+    pub fn is_choosing_word(&self) -> bool {
+        self.turn_phase == TurnPhase::ChoosingWord
+    }
+
+    /// offer the next words to the drawing user, starting the word choice timer.
+    /// returns false if there are no words left to choose from.
+    fn offer_word_choices(&mut self) -> bool {
+        let choice_count = min(self.settings.word_choice_count, self.remaining_words.len());
+        if choice_count == 0 {
+            return false;
+        }
+        self.word_choices = self.remaining_words.drain(..choice_count).collect();
+        self.set_current_word(String::new());
+        self.turn_phase = TurnPhase::ChoosingWord;
+        self.round_end_time = get_time_now() + self.settings.word_choice_duration;
+        true
+    }
+
+    /// choose the word with the given index out of the offered words and start the drawing phase.
+    /// the words that weren't chosen go back into the pool of remaining words.
+    /// returns false if no word is currently being chosen or the index is invalid.
+    pub fn choose_word(&mut self, idx: usize) -> bool {
+        if !self.is_choosing_word() || idx >= self.word_choices.len() {
+            return false;
+        }
+        let word = self.word_choices.remove(idx);
+        self.remaining_words.append(&mut self.word_choices);
+        self.set_current_word(word);
+        self.turn_phase = TurnPhase::Drawing;
+        self.round_end_time = get_time_now() + self.settings.round_duration;
+        true
+    }
+
     /// reveals a random character, as long as that doesn't reveal half of the word
     pub fn reveal_random_char(&mut self) {
         if self.revealed_characters.len() < self.current_word.len() / 2 {
@@ -79,8 +197,9 @@ impl SkribblState {
         self.player_states.get(username).map(|x| x.has_solved) == Some(true)
     }
 
-    pub fn remove_user(&mut self, username: &Username) {
-        self.player_states.remove(username);
+    /// remove a user from the game, returning their state
+    pub fn remove_user(&mut self, username: &Username) -> Option<PlayerState> {
+        let player_state = self.player_states.remove(username);
         let left_player_idx = self
             .remaining_users
             .iter()
@@ -91,12 +210,13 @@ impl SkribblState {
         if let Some(idx) = left_player_idx {
             self.remaining_users.remove(idx);
         }
+        player_state
     }
 
-    pub fn add_player(&mut self, username: Username) {
+    pub fn add_player(&mut self, username: Username, player_state: PlayerState) {
         if !self.player_states.contains_key(&username) {
             self.remaining_users.push(username.clone());
-            self.player_states.insert(username, PlayerState::default());
+            self.player_states.insert(username, player_state);
         }
     }
 
@@ -104,7 +224,8 @@ impl SkribblState {
         self.drawing_user == *username
     }
     pub fn can_guess(&self, username: &Username) -> bool {
-        !self.is_drawing(username)
+        !self.is_choosing_word()
+            && !self.is_drawing(username)
             && !self
                 .player_states
                 .get(username)
@@ -112,44 +233,65 @@ impl SkribblState {
                 .unwrap_or(false)
     }
 
-    pub fn next_turn(&mut self) -> &Username {
-        let remaining_time = self.remaining_time();
+    /// finish the current turn and start the next one, moving on to the next round if everyone drew.
+    /// returns false if there is no turn left, meaning the game is over.
+    pub fn next_turn(&mut self) -> bool {
+        let score_increase = self
+            .settings
+            .calculate_score_increase(self.remaining_time());
+        let drawer_bonus = self.settings.drawer_bonus;
         self.player_states
             .get_mut(&self.drawing_user)
             .map(|drawing_user| {
-                drawing_user.score += 50;
-                drawing_user.on_solve(remaining_time);
+                drawing_user.score += drawer_bonus;
+                drawing_user.on_solve(score_increase);
             });
 
-        let new_word = self.remaining_words.remove(0);
-        self.set_current_word(new_word);
-        self.round_end_time = get_time_now() + ROUND_DURATION;
-        if self.remaining_users.len() == 0 {
+        if self.remaining_users.is_empty() {
+            if self.round >= self.settings.number_of_rounds || self.player_states.is_empty() {
+                return false;
+            }
+            self.round += 1;
             self.remaining_users = self.player_states.keys().cloned().collect();
         }
+
+        if !self.offer_word_choices() {
+            return false;
+        }
         self.drawing_user = self.remaining_users.remove(0);
         self.player_states
             .iter_mut()
             .for_each(|(_, player)| player.has_solved = false);
-        &self.drawing_user
+        true
+    }
+
+    /// all players with their states, sorted by score with the best player first.
+    pub fn ranking(&self) -> Vec<(&Username, &PlayerState)> {
+        let mut ranking = self.player_states.iter().collect::<Vec<_>>();
+        ranking.sort_by(|(name_a, a), (name_b, b)| b.score.cmp(&a.score).then(name_a.cmp(name_b)));
+        ranking
     }
 
-    pub fn new(users: Vec<Username>, mut words: Vec<String>) -> Self {
+    pub fn new(users: Vec<Username>, mut words: Vec<String>, settings: GameSettings) -> Self {
         let mut rng = rand::thread_rng();
         words.shuffle(&mut rng);
-        let current_word = words.remove(0);
         let mut state = SkribblState {
-            current_word: current_word.clone(),
+            current_word: String::new(),
             revealed_characters: Vec::new(),
+            word_choices: Vec::new(),
+            turn_phase: TurnPhase::ChoosingWord,
             drawing_user: users[0].clone(),
             remaining_users: users.iter().cloned().skip(1).collect::<Vec<_>>(),
             player_states: HashMap::new(),
-            round_end_time: get_time_now() + ROUND_DURATION,
+            round_end_time: get_time_now() + settings.round_duration,
             remaining_words: words,
+            round: 1,
+            settings,
         };
         for user in users {
             state.player_states.insert(user, PlayerState::default());
         }
+        state.offer_word_choices();
         state
     }
 }
@@ -177,12 +319,8 @@ impl Default for PlayerState {
 }
 
 impl PlayerState {
-    pub fn on_solve(&mut self, remaining_time: u32) {
-        self.score += calculate_score_increase(remaining_time);
+    pub fn on_solve(&mut self, score_increase: u32) {
+        self.score += score_increase;
         self.has_solved = true;
     }
 }
-
-pub fn calculate_score_increase(remaining_time: u32) -> u32 {
-    50 + (((remaining_time as f64 / ROUND_DURATION as f64) * 100f64) as u32 / 2u32)
-}
@@ -0,0 +1,344 @@
+use crate::{
+    client::{
+        app::{AppCanvas, Tool},
+        ui::{CanvasWidget, ColorSupport, Viewport},
+    },
+    data::{Canvas, CanvasColor},
+};
+use std::{borrow::Cow, fmt::Write as _, path::Path};
+use tui::{
+    buffer::{Buffer, Cell},
+    layout::Rect,
+    style::Color,
+    widgets::{Block, Widget},
+};
+
+/// the shortest time a frame is shown for, in milliseconds. changes that happen faster are shown together
+const MIN_FRAME_DURATION: u64 = 100;
+
+/// the longest time a frame is shown for, so the timelapse skips over pauses in drawing
+const MAX_FRAME_DURATION: u64 = 2000;
+
+/// how long the finished drawing is shown at the end
+const END_DURATION: u64 = 3000;
+
+/// how many pixels wide a terminal cell is in gifs. cells are twice as high as they are wide.
+const CELL_WIDTH: usize = 4;
+
+/// the canvas at some point while it was drawn
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// when the canvas looked like this, in milliseconds
+    pub time: u64,
+    pub canvas: Canvas,
+}
+
+/// save the frames to the given file, as an animated gif or an asciinema recording depending on its extension.
+/// the time between frames is divided by `speed`.
+pub fn save(frames: &[Frame], path: &Path, speed: f64, half_blocks: bool) -> Result<(), String> {
+    if frames.is_empty() {
+        return Err("there is nothing to save".to_string());
+    }
+    let contents = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gif") => gif(&render(frames, speed, half_blocks))?,
+        Some("cast") => asciicast(&render(frames, speed, half_blocks)).into_bytes(),
+        _ => return Err("timelapses can only be saved as .gif or .cast".to_string()),
+    };
+    std::fs::write(path, contents).map_err(|err| err.to_string())
+}
+
+/// render the frames the way the canvas is shown in the terminal, together with how long each is shown for
+fn render(frames: &[Frame], speed: f64, half_blocks: bool) -> Vec<(Buffer, u64)> {
+    let times = frames
+        .iter()
+        .map(|frame| ((frame.time - frames[0].time) as f64 / speed) as u64)
+        .collect::<Vec<_>>();
+    let mut rendered: Vec<(Buffer, u64)> = Vec::new();
+    let mut duration = 0;
+    for (idx, frame) in frames.iter().enumerate() {
+        let is_last = idx + 1 == frames.len();
+        duration += if is_last {
+            END_DURATION
+        } else {
+            u64::min(times[idx + 1] - times[idx], MAX_FRAME_DURATION)
+        };
+        // frames that would only be shown very briefly are skipped in favour of the next one
+        if duration < MIN_FRAME_DURATION && !is_last {
+            continue;
+        }
+        let buffer = render_canvas(&frame.canvas, half_blocks);
+        match rendered.last_mut() {
+            Some((previous, previous_duration)) if *previous == buffer => {
+                *previous_duration += duration
+            }
+            _ => rendered.push((buffer, duration)),
+        }
+        duration = 0;
+    }
+    rendered
+}
+
+/// draw the canvas using a `CanvasWidget` the size of the canvas
+fn render_canvas(canvas: &Canvas, half_blocks: bool) -> Buffer {
+    let canvas = AppCanvas::view_only(canvas.clone());
+    let (width, height) = canvas.dimensions;
+    let area = Rect::new(0, 0, width as u16, height.div_ceil(2) as u16);
+    let mut buffer = Buffer::empty(area);
+    CanvasWidget::new(
+        &canvas,
+        Viewport::new(area, canvas.dimensions),
+        half_blocks,
+        ColorSupport::TrueColor,
+        Tool::Brush,
+        0,
+        Block::default(),
+    )
+    .render(area, &mut buffer);
+    buffer
+}
+
+/// the area of the cells that differ between the two buffers, or all of them without a previous buffer
+fn changed_area(previous: Option<&Buffer>, buffer: &Buffer) -> Rect {
+    let area = buffer.area;
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return area,
+    };
+    let (mut left, mut top, mut right, mut bottom) = (area.right(), area.bottom(), 0, 0);
+    for y in area.top()..area.bottom() {
+        for x in area.left()..area.right() {
+            if previous.get(x, y) != buffer.get(x, y) {
+                left = left.min(x);
+                top = top.min(y);
+                right = right.max(x + 1);
+                bottom = bottom.max(y + 1);
+            }
+        }
+    }
+    if left >= right {
+        // consecutive frames always differ, but a gif frame can't be empty either way
+        return Rect::new(0, 0, 1, 1);
+    }
+    Rect::new(left, top, right - left, bottom - top)
+}
+
+/// encode the frames as a looping gif, each terminal cell becoming `CELL_WIDTH` pixels wide and twice as high.
+/// all colors are shown as the closest color of the 256 color palette.
+fn gif(frames: &[(Buffer, u64)]) -> Result<Vec<u8>, String> {
+    let area = frames[0].0.area;
+    // the 256 color palette, the background being its first color
+    let palette = (0..=255)
+        .flat_map(|idx| {
+            let (r, g, b) = CanvasColor::Indexed(idx).rgb();
+            vec![r, g, b]
+        })
+        .collect::<Vec<_>>();
+    let (cell_width, cell_height) = (CELL_WIDTH as u16, CELL_WIDTH as u16 * 2);
+    let mut encoder = gif::Encoder::new(
+        Vec::new(),
+        area.width * cell_width,
+        area.height * cell_height,
+        &palette,
+    )
+    .map_err(|err| err.to_string())?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(|err| err.to_string())?;
+
+    let mut previous = None;
+    for (buffer, duration) in frames {
+        // frames are drawn on top of each other, so each only needs the cells that changed
+        let changed = changed_area(previous, buffer);
+        previous = Some(buffer);
+
+        let mut pixels = Vec::new();
+        for y in changed.top()..changed.bottom() {
+            let halves = (changed.left()..changed.right())
+                .map(|x| cell_halves(buffer.get(x, y)))
+                .collect::<Vec<_>>();
+            for half in 0..2 {
+                for _ in 0..CELL_WIDTH {
+                    for (top, bottom) in &halves {
+                        let color = if half == 0 { *top } else { *bottom };
+                        pixels.extend((0..CELL_WIDTH).map(|_| color));
+                    }
+                }
+            }
+        }
+        let frame = gif::Frame {
+            delay: ((duration + 5) / 10) as u16,
+            dispose: gif::DisposalMethod::Keep,
+            left: changed.x * cell_width,
+            top: changed.y * cell_height,
+            width: changed.width * cell_width,
+            height: changed.height * cell_height,
+            buffer: Cow::Owned(pixels),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame).map_err(|err| err.to_string())?;
+    }
+    encoder.into_inner().map_err(|err| err.to_string())
+}
+
+/// the palette indices of the top and bottom half of a cell
+fn cell_halves(cell: &Cell) -> (u8, u8) {
+    let fg = palette_index(cell.style.fg);
+    let bg = palette_index(cell.style.bg);
+    match cell.symbol.as_str() {
+        "▀" => (fg, bg),
+        "▄" => (bg, fg),
+        _ => (bg, bg),
+    }
+}
+
+/// the closest color of the 256 color palette. the default color is the background, which is black.
+fn palette_index(color: Color) -> u8 {
+    match color {
+        Color::Indexed(idx) => idx,
+        Color::Rgb(r, g, b) => match CanvasColor::Rgb(r, g, b).to_indexed() {
+            CanvasColor::Indexed(idx) => idx,
+            _ => 0,
+        },
+        named => ansi_index(named).unwrap_or(0),
+    }
+}
+
+/// the index of a named color in the 16 colors every terminal supports
+fn ansi_index(color: Color) -> Option<u8> {
+    CanvasColor::ANSI
+        .iter()
+        .position(|&ansi| Color::from(ansi) == color)
+        .map(|idx| idx as u8)
+}
+
+/// encode the frames as an asciinema v2 recording, showing the same cells a terminal would
+fn asciicast(frames: &[(Buffer, u64)]) -> String {
+    let area = frames[0].0.area;
+    let mut cast = serde_json::json!({
+        "version": 2,
+        "width": area.width,
+        "height": area.height,
+        "env": { "TERM": "xterm-256color" },
+    })
+    .to_string();
+    cast.push('\n');
+
+    let mut time = 0;
+    let mut previous: Option<&Buffer> = None;
+    for (buffer, duration) in frames {
+        let mut output = String::new();
+        if previous.is_none() {
+            output.push_str("\x1b[2J");
+        }
+        // the cursor and style after the last written cell, so they're only changed when needed
+        let mut cursor = None;
+        let mut style = None;
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let cell = buffer.get(x, y);
+                if previous.is_some_and(|previous| previous.get(x, y) == cell) {
+                    continue;
+                }
+                if cursor != Some((x, y)) {
+                    let _ = write!(output, "\x1b[{};{}H", y + 1, x + 1);
+                }
+                if style != Some(cell.style) {
+                    let _ = write!(
+                        output,
+                        "\x1b[0;{};{}m",
+                        sgr_color(cell.style.fg, false),
+                        sgr_color(cell.style.bg, true)
+                    );
+                    style = Some(cell.style);
+                }
+                output.push_str(&cell.symbol);
+                cursor = Some((x + 1, y));
+            }
+        }
+        output.push_str("\x1b[0m");
+        cast.push_str(&serde_json::json!([time as f64 / 1000.0, "o", output]).to_string());
+        cast.push('\n');
+        time += duration;
+        previous = Some(buffer);
+    }
+    // keep the last frame on screen for as long as it should be shown
+    cast.push_str(&serde_json::json!([time as f64 / 1000.0, "o", ""]).to_string());
+    cast.push('\n');
+    cast
+}
+
+/// the parameters of a select graphic rendition sequence that set the given color
+fn sgr_color(color: Color, background: bool) -> String {
+    let offset = if background { 10 } else { 0 };
+    match color {
+        Color::Rgb(r, g, b) => format!("{};2;{};{};{}", 38 + offset, r, g, b),
+        Color::Indexed(idx) => format!("{};5;{}", 38 + offset, idx),
+        named => match ansi_index(named) {
+            Some(idx) if idx < 8 => (30 + offset + idx as u16).to_string(),
+            Some(idx) => (90 + offset + idx as u16 - 8).to_string(),
+            None => (39 + offset).to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Coord, DrawOp, StrokeBatch};
+
+    fn frames() -> Vec<Frame> {
+        let mut canvas = Canvas::new((8, 6));
+        let mut frames = vec![Frame {
+            time: 0,
+            canvas: canvas.clone(),
+        }];
+        let mut stroke = StrokeBatch::new(1, Some(CanvasColor::Red), 1, Coord(1, 1));
+        stroke.points.push(Coord(6, 4));
+        canvas.apply(&DrawOp::Stroke(stroke));
+        frames.push(Frame { time: 500, canvas });
+        frames
+    }
+
+    #[test]
+    fn gif_frames_only_cover_what_changed() {
+        let gif = gif(&render(&frames(), 1.0, false)).unwrap();
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(&gif[..]).unwrap();
+        assert_eq!(
+            (decoder.width(), decoder.height()),
+            (8 * CELL_WIDTH as u16, 3 * CELL_WIDTH as u16 * 2)
+        );
+
+        let first = decoder.read_next_frame().unwrap().unwrap().clone();
+        assert_eq!((first.left, first.top), (0, 0));
+        assert_eq!(first.buffer.len(), 8 * 3 * CELL_WIDTH * CELL_WIDTH * 2);
+        assert!(first.buffer.iter().all(|&idx| idx == 0));
+        assert_eq!(first.delay, 50);
+
+        let second = decoder.read_next_frame().unwrap().unwrap().clone();
+        assert!(second.width < 8 * CELL_WIDTH as u16 || second.height < 24);
+        assert!(second.buffer.iter().any(|&idx| idx != 0));
+        assert_eq!(second.delay, 300);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn asciicast_events_are_valid_json_in_order() {
+        let cast = asciicast(&render(&frames(), 1.0, false));
+        let mut lines = cast.lines();
+        let header: serde_json::Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!(header["version"], 2);
+        assert_eq!(
+            (header["width"].as_u64(), header["height"].as_u64()),
+            (Some(8), Some(3))
+        );
+
+        let events = lines
+            .map(|line| serde_json::from_str::<(f64, String, String)>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 3);
+        assert!(events.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert_eq!(events[2].2, "");
+    }
+}